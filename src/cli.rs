@@ -1,9 +1,11 @@
-use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
 
-pub fn get_matches<'a>(
-    default_config_path: &'a str,
-    default_track_path: &'a str,
-) -> ArgMatches<'a> {
+use std::process;
+
+use crate::failure::ExitCode;
+use repo_rs::ops::MAX_JOBS;
+
+pub fn get_matches<'a>(default_track_path: &'a str) -> ArgMatches<'a> {
     let app = App::new("repo-rs")
         .about("Manage multiple git repositories")
         .author(crate_authors!())
@@ -13,16 +15,90 @@ pub fn get_matches<'a>(
         .global_setting(AppSettings::ColoredHelp)
         .arg(
             Arg::with_name("config")
-                .help("sets the config file to use")
+                .help(
+                    "sets the config file to use. If not specified, this is \
+                     $REPO_RS_CONFIG, the nearest .repo-rs.toml workspace file in the \
+                     current directory or its parents, $XDG_CONFIG_HOME/repo-rs/config.*, \
+                     or ~/.repo-rs.json, whichever is found first",
+                )
                 .takes_value(true)
-                .default_value(default_config_path)
                 .short("c")
                 .long("config")
                 .global(true),
         )
+        .arg(
+            Arg::with_name("timeout")
+                .help(
+                    "The maximum number of seconds a single command may run in a \
+                     repo before it is killed. This replaces any timeout set for a \
+                     repo or in the settings of the config. [env: REPO_RS_TIMEOUT]",
+                )
+                .takes_value(true)
+                .validator(is_seconds)
+                .long("timeout")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .help("The maximum number of repos to operate on concurrently [env: REPO_RS_JOBS]")
+                .takes_value(true)
+                .validator(is_jobs)
+                .short("j")
+                .long("jobs")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("How progress and results are displayed [env: REPO_RS_FORMAT]")
+                .takes_value(true)
+                .possible_values(&["pretty", "plain", "json"])
+                .long("format")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("color")
+                .help("When to use colors in output [env: REPO_RS_COLOR]")
+                .takes_value(true)
+                .possible_values(&["auto", "always", "never"])
+                .long("color")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .help(
+                    "Only operate on repos with this tag. May be given multiple times. \
+                     Pass an empty tag to operate on all repos regardless of the \
+                     default tags in the config. [env: REPO_RS_TAGS]",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .short("t")
+                .long("tag")
+                .global(true),
+        )
         .subcommand(
             SubCommand::with_name("gh")
                 .about("interacts with github")
+                .arg(
+                    Arg::with_name("api-url")
+                        .help(
+                            "The base URL of the GitHub API, such as \
+                             https://github.example.com/api/v3 for GitHub Enterprise",
+                        )
+                        .long("api-url")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("per-page")
+                        .help(
+                            "How many items to request per page of a list, up to 100 \
+                             [env: REPO_RS_GITHUB_PER_PAGE]",
+                        )
+                        .long("per-page")
+                        .takes_value(true)
+                        .validator(is_per_page),
+                )
                 .subcommand(
                     SubCommand::with_name("list")
                         .about("lists repos from an organization")
@@ -32,6 +108,119 @@ pub fn get_matches<'a>(
                                 .index(1)
                                 .required(true),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name("prs")
+                        .about("lists open pull requests of tracked repos")
+                        .arg(
+                            Arg::with_name("mine")
+                                .help("Only list pull requests opened by you")
+                                .short("m")
+                                .long("mine"),
+                        )
+                        .arg(
+                            Arg::with_name("review-requested")
+                                .help("Only list pull requests awaiting your review")
+                                .short("r")
+                                .long("review-requested"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("pr")
+                        .about("manages pull requests")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(
+                            SubCommand::with_name("create")
+                                .about(
+                                    "pushes the current branch of each repo that is ahead of \
+                                     its default branch and opens a pull request for it",
+                                )
+                                .arg(
+                                    Arg::with_name("title")
+                                        .help("The title of the pull requests")
+                                        .long("title")
+                                        .takes_value(true)
+                                        .required(true),
+                                )
+                                .arg(
+                                    Arg::with_name("body")
+                                        .help(
+                                            "The description of the pull requests, to which \
+                                             links to each other are added",
+                                        )
+                                        .short("b")
+                                        .long("body")
+                                        .takes_value(true),
+                                )
+                                .arg(
+                                    Arg::with_name("draft")
+                                        .help("Open the pull requests as drafts")
+                                        .short("d")
+                                        .long("draft"),
+                                ),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("forge")
+                .about("interacts with github, gitlab or gitea")
+                .arg(
+                    Arg::with_name("forge")
+                        .help("The name of a forge in the `forges` settings of the config")
+                        .short("f")
+                        .long("forge")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("kind")
+                        .help("The kind of forge, if not configured. Defaults to github.")
+                        .short("k")
+                        .long("kind")
+                        .takes_value(true)
+                        .possible_values(&["github", "gitlab", "gitea", "forgejo"]),
+                )
+                .arg(
+                    Arg::with_name("api-url")
+                        .help(
+                            "The base URL of the forge's API, such as \
+                             https://gitlab.example.com/api/v4",
+                        )
+                        .long("api-url")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("per-page")
+                        .help(
+                            "How many items to request per page of a list, up to 100 or the \
+                             forge's lower maximum",
+                        )
+                        .long("per-page")
+                        .takes_value(true)
+                        .validator(is_per_page),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("lists repos from an organization, group or user")
+                        .arg(
+                            Arg::with_name("owner")
+                                .help("The organization, group or user")
+                                .index(1)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("user")
+                                .help(
+                                    "List the repos of a user rather than an organization or group",
+                                )
+                                .short("u")
+                                .long("user"),
+                        )
+                        .arg(
+                            Arg::with_name("archived")
+                                .help("Include archived repos")
+                                .short("a")
+                                .long("archived"),
+                        ),
                 ),
         )
         .subcommand(SubCommand::with_name("list").about("lists tracked repos"))
@@ -43,6 +232,11 @@ pub fn get_matches<'a>(
                         .help("Show status for all tracked repos, even if a repo is not dirty")
                         .short("a")
                         .long("all"),
+                )
+                .arg(
+                    Arg::with_name("raw")
+                        .help("Write command output as-is instead of decoding it as UTF-8")
+                        .long("raw"),
                 ),
         )
         .subcommand(
@@ -67,7 +261,8 @@ pub fn get_matches<'a>(
                         .takes_value(true)
                         .required(false)
                         .short("k")
-                        .long("key"),
+                        .long("key")
+                        .conflicts_with("recursive"),
                 )
                 .arg(
                     Arg::with_name("branch")
@@ -75,7 +270,8 @@ pub fn get_matches<'a>(
                         .takes_value(true)
                         .required(false)
                         .short("b")
-                        .long("branch"),
+                        .long("branch")
+                        .conflicts_with("recursive"),
                 )
                 .arg(
                     Arg::with_name("remote")
@@ -87,16 +283,189 @@ pub fn get_matches<'a>(
                         .required(false)
                         .short("r")
                         .long("remote"),
+                )
+                .arg(
+                    Arg::with_name("tags")
+                        .help("A tag to apply to the tracked repo. May be given multiple times.")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("tag")
+                        .long("add-tag"),
+                )
+                .arg(
+                    Arg::with_name("relative")
+                        .help(
+                            "Store the path relative to the workspace root, or to the home \
+                             directory if the repo is not under the workspace root",
+                        )
+                        .long("relative"),
+                )
+                .arg(
+                    Arg::with_name("namespaced")
+                        .help(
+                            "Derive the key as owner/name from the remote URL instead of \
+                             from the directory name",
+                        )
+                        .short("n")
+                        .long("namespaced"),
+                )
+                .arg(
+                    Arg::with_name("recursive")
+                        .help("Track every repo found in the directory tree under the path")
+                        .short("R")
+                        .long("recursive"),
+                )
+                .arg(
+                    Arg::with_name("max-depth")
+                        .help("How many directories deep to look for repos")
+                        .takes_value(true)
+                        .default_value("3")
+                        .validator(is_depth)
+                        .long("max-depth"),
+                )
+                .arg(
+                    Arg::with_name("ignore")
+                        .help(
+                            "A glob matching directory names or paths relative to the \
+                             searched path to skip. May be given multiple times.",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("pattern")
+                        .validator(is_pattern)
+                        .requires("recursive")
+                        .long("ignore"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .help("Track the repos found without asking for confirmation")
+                        .short("y")
+                        .long("yes")
+                        .requires("recursive"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("untrack")
-                .about("stop tracking a repo")
+                .about("stop tracking repos")
+                .arg(
+                    Arg::with_name("keys")
+                        .help(
+                            "The keys of the repos to untrack. Each may be an unambiguous \
+                             prefix of a key or a glob such as 'org/*'.",
+                        )
+                        .index(1)
+                        .multiple(true)
+                        .required_unless("missing"),
+                )
+                .arg(
+                    Arg::with_name("missing")
+                        .help("Untrack every repo whose path no longer exists")
+                        .long("missing"),
+                )
+                .arg(
+                    Arg::with_name("delete")
+                        .help(
+                            "Also delete the working trees of the untracked repos. Repos \
+                             with uncommitted, stashed or unpushed work are left alone.",
+                        )
+                        .long("delete"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .help("Delete working trees without asking for confirmation")
+                        .short("y")
+                        .long("yes")
+                        .requires("delete"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set")
+                .about("change the settings of a tracked repo")
                 .arg(
                     Arg::with_name("key")
-                        .help("The key of the repo to untrack.")
+                        .help("The key of the repo to change, or an unambiguous prefix of it.")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("new-key")
+                        .help("A new key to track the repo under")
+                        .takes_value(true)
+                        .value_name("key")
+                        .long("key"),
+                )
+                .arg(
+                    Arg::with_name("branch")
+                        .help("The branch to track. Must exist locally or on the remote")
+                        .takes_value(true)
+                        .short("b")
+                        .long("branch"),
+                )
+                .arg(
+                    Arg::with_name("remote")
+                        .help("The remote to track. Must exist in the repo")
+                        .takes_value(true)
+                        .short("r")
+                        .long("remote"),
+                )
+                .arg(
+                    Arg::with_name("add-tags")
+                        .help("A tag to add to the repo. May be given multiple times.")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("tag")
+                        .long("add-tag"),
+                )
+                .arg(
+                    Arg::with_name("remove-tags")
+                        .help("A tag to remove from the repo. May be given multiple times.")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("tag")
+                        .long("remove-tag"),
+                )
+                .group(
+                    ArgGroup::with_name("changes")
+                        .args(&["new-key", "branch", "remote", "add-tags", "remove-tags"])
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("relocate")
+                .about("rewrite the paths of all tracked repos under a directory that has moved")
+                .arg(
+                    Arg::with_name("old-root")
+                        .help("The directory the repos used to be in")
                         .index(1)
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("new-root")
+                        .help("The directory the repos are in now. May start with ~")
+                        .index(2)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("Show the paths that would be rewritten without saving")
+                        .long("dry-run"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("check tracked repos for broken config entries")
+                .arg(
+                    Arg::with_name("fix")
+                        .help(
+                            "Repair broken entries where possible, and untrack the ones \
+                             that point at missing or duplicate paths",
+                        )
+                        .long("fix"),
                 ),
         )
         .subcommand(
@@ -111,6 +480,26 @@ pub fn get_matches<'a>(
                         )
                         .short("s")
                         .long("stash"),
+                )
+                .arg(
+                    Arg::with_name("no-stash")
+                        .help("Do not stash changes, even if stashing is enabled in the config")
+                        .conflicts_with("stash")
+                        .long("no-stash"),
+                )
+                .arg(
+                    Arg::with_name("batch")
+                        .help(
+                            "Fail instead of letting credential helpers prompt for a \
+                             password. This is the default when stdin is not a terminal.",
+                        )
+                        .long("batch"),
+                )
+                .arg(
+                    Arg::with_name("no-batch")
+                        .help("Allow credential helpers to prompt, even if stdin is not a terminal")
+                        .conflicts_with("batch")
+                        .long("no-batch"),
                 ),
         )
         .subcommand(
@@ -118,10 +507,73 @@ pub fn get_matches<'a>(
                 .about("run a command in all tracked repos")
                 .arg(
                     Arg::with_name("quiet")
-                        .help("Ignore failures")
+                        .help("Exit successfully even if the command failed in some repos")
                         .short("q")
                         .long("quiet"),
                 )
+                .arg(
+                    Arg::with_name("raw")
+                        .help("Write command output as-is instead of decoding it as UTF-8")
+                        .long("raw"),
+                )
+                .arg(
+                    Arg::with_name("shell")
+                        .help(
+                            "Run the command through `$SHELL -c`, allowing pipes, `&&` and \
+                             variable expansion. Pass the command line as one argument; \
+                             any further arguments are appended quoted. The variables \
+                             REPO_KEY, REPO_PATH, REPO_REMOTE and REPO_BRANCH are always \
+                             available, and the placeholders {key}, {path}, {remote} and \
+                             {branch} are substituted as quoted words, so don't put them \
+                             inside quotes.",
+                        )
+                        .long("shell"),
+                )
+                .arg(
+                    Arg::with_name("fail-fast")
+                        .help("Cancel the command in all remaining repos after the first failure")
+                        .long("fail-fast"),
+                )
+                .arg(
+                    Arg::with_name("ok-exit-codes")
+                        .help(
+                            "A comma-separated list of non-zero exit codes that \
+                             should not be treated as failures",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .require_delimiter(true)
+                        .validator(is_exit_code)
+                        .long("ok-exit-codes"),
+                )
+                .arg(
+                    Arg::with_name("if-exists")
+                        .help(
+                            "Only run in repos where this path exists (relative to the repo root)",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("path")
+                        .long("if-exists"),
+                )
+                .arg(
+                    Arg::with_name("if-dirty")
+                        .help("Only run in repos with unstaged changes")
+                        .long("if-dirty"),
+                )
+                .arg(
+                    Arg::with_name("if-branch")
+                        .help("Only run in repos that have this branch checked out")
+                        .takes_value(true)
+                        .value_name("branch")
+                        .long("if-branch"),
+                )
+                .arg(
+                    Arg::with_name("if-ahead")
+                        .help("Only run in repos whose current branch is ahead of its upstream")
+                        .long("if-ahead"),
+                )
                 .arg(
                     Arg::with_name("cmd")
                         .multiple(true)
@@ -130,5 +582,52 @@ pub fn get_matches<'a>(
                         .last(true),
                 ),
         );
-    app.get_matches()
+
+    match app.get_matches_safe() {
+        Ok(matches) => matches,
+        // help and version are also reported as errors, but aren't failures
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            process::exit(ExitCode::Usage as i32)
+        }
+    }
+}
+
+fn is_exit_code(val: String) -> Result<(), String> {
+    val.parse::<i32>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a valid exit code", val))
+}
+
+fn is_seconds(val: String) -> Result<(), String> {
+    val.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a valid number of seconds", val))
+}
+
+fn is_jobs(val: String) -> Result<(), String> {
+    match val.parse::<usize>() {
+        Ok(jobs) if (1..=MAX_JOBS).contains(&jobs) => Ok(()),
+        _ => Err(format!("'{}' is not a positive number of jobs", val)),
+    }
+}
+
+fn is_per_page(val: String) -> Result<(), String> {
+    match val.parse::<u32>() {
+        Ok(size) if (1..=100).contains(&size) => Ok(()),
+        _ => Err(format!("'{}' is not a page size from 1 to 100", val)),
+    }
+}
+
+fn is_depth(val: String) -> Result<(), String> {
+    val.parse::<usize>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a valid depth", val))
+}
+
+fn is_pattern(val: String) -> Result<(), String> {
+    glob::Pattern::new(&val)
+        .map(|_| ())
+        .map_err(|e| format!("'{}' is not a valid pattern: {}", val, e))
 }
@@ -1,41 +1,284 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use glob::Pattern;
 
-use prettytable::{format, row, Table};
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{RepoRsError, Result};
+use crate::repo::{expand_home, namespaced_key, Repo};
+use crate::settings::Settings;
+
+/// The current version of the config file schema
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades a config from one schema version to the next. The migration at
+/// index `n` upgrades a config from version `n` to version `n + 1`.
+type Migration = fn(Value) -> Result<Value>;
+
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+/// Version 0 configs have no `version` field, but are otherwise identical
+fn migrate_v0_to_v1(mut value: Value) -> Result<Value> {
+    value["version"] = Value::from(1);
+    Ok(value)
+}
+
+/// The name of the workspace file searched for in the current directory and
+/// its ancestors
+pub const WORKSPACE_FILE: &str = ".repo-rs.toml";
+
+/// Determines which config file to use when one is not given on the command
+/// line. In order of preference, this is the file named by `REPO_RS_CONFIG`,
+/// the nearest workspace file, `$XDG_CONFIG_HOME/repo-rs/config.*`, or the
+/// legacy `~/.repo-rs.json`. If none of these exist, a new config will be
+/// created in the XDG config directory.
+pub fn discover(cwd: &Path) -> PathBuf {
+    if let Some(path) = env::var_os("REPO_RS_CONFIG") {
+        return PathBuf::from(path);
+    }
+
+    if let Some(path) = find_workspace(cwd) {
+        return path;
+    }
+
+    let config_dir = xdg_config_dir().join("repo-rs");
+    for extension in &["toml", "yaml", "yml", "json"] {
+        let path = config_dir.join(format!("config.{}", extension));
+        if path.is_file() {
+            return path;
+        }
+    }
 
-use crate::error::Result;
-use crate::repo::Repo;
+    if let Some(legacy) = dirs::home_dir().map(|h| h.join(".repo-rs.json")) {
+        if legacy.is_file() {
+            return legacy;
+        }
+    }
 
+    config_dir.join("config.toml")
+}
+
+/// Finds the nearest workspace file in `dir` or any of its ancestors
+fn find_workspace(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(WORKSPACE_FILE))
+        .find(|p| p.is_file())
+}
+
+/// The directory repo-rs keeps cached data in, such as API responses
+pub fn cache_dir() -> PathBuf {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::home_dir()
+            .expect("could not determine home directory")
+            .join(".cache"),
+    };
+    base.join("repo-rs")
+}
+
+pub(crate) fn xdg_config_dir() -> PathBuf {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::home_dir()
+            .expect("could not determine home directory")
+            .join(".config"),
+    }
+}
+
+/// The serialization formats supported for config files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Determines the format of a config file from its extension, falling back
+    /// to JSON for unknown extensions
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+
+    fn parse(&self, content: &str) -> Result<Value> {
+        Ok(match *self {
+            Format::Json => serde_json::from_str(content)?,
+            Format::Toml => toml::from_str(content)?,
+            Format::Yaml => serde_yaml::from_str(content)?,
+        })
+    }
+
+    fn serialize(&self, config: &Config) -> Result<Vec<u8>> {
+        Ok(match *self {
+            Format::Json => serde_json::to_vec_pretty(config)?,
+            Format::Toml => toml::to_string_pretty(config)?.into_bytes(),
+            Format::Yaml => serde_yaml::to_string(config)?.into_bytes(),
+        })
+    }
+}
+
+/// The tracked repos and command defaults stored in a config file
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
+    #[serde(default)]
     pub repos: HashMap<String, Repo>,
+    /// The resolved workspace root, set when the config is loaded
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+    /// Whether the config file did not exist and was created when loading
+    #[serde(skip)]
+    pub created: bool,
+    /// The schema migration performed when loading, if any
+    #[serde(skip)]
+    pub migrated: Option<Migrated>,
+    /// A hash of the file content this config was loaded from, used to detect
+    /// modifications made by something else before saving
+    #[serde(skip)]
+    fingerprint: Option<u64>,
+}
+
+/// An upgrade of an older config to the current schema version
+#[derive(Debug, Clone)]
+pub struct Migrated {
+    /// The version the config was migrated from
+    pub from: u32,
+    /// Where the original config is copied to when the migrated config is
+    /// first saved
+    pub backup: PathBuf,
+}
+
+/// An exclusive advisory lock on a config file, released when dropped
+pub struct ConfigLock {
+    _file: File,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            settings: Settings::default(),
+            repos: HashMap::new(),
+            root: None,
+            created: false,
+            migrated: None,
+            fingerprint: None,
+        }
+    }
 }
 
 impl Config {
+    /// Loads the config at `path`, creating an empty one if it doesn't exist.
+    /// An older config is migrated to the current schema version in memory
+    /// only, and rewritten the next time it is saved.
     pub fn new(path: &Path) -> Result<Self> {
         // if the path doesn't exist, create the file first
-        if !path.exists() {
-            println!(
-                "Config file not found at path \"{}\". Creating one.",
-                path.to_str().unwrap_or("error displaying path")
-            );
-            let mut new_config = File::create(path)?;
-            new_config.write_all(b"{\"repos\": {}}")?;
+        let created = !path.exists();
+        if created {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            Config::default().save(path)?;
+        }
+
+        let content = fs::read_to_string(path)?;
+        let (mut config, version) = Config::parse(&content, path)?;
+        config.fingerprint = Some(fingerprint(content.as_bytes()));
+        config.created = created;
+
+        if version < CONFIG_VERSION {
+            config.migrated = Some(Migrated {
+                from: version,
+                backup: with_suffix(path, &format!(".v{}.bak", version)),
+            });
+        }
+
+        // relative repo paths are relative to the workspace root, which is
+        // itself relative to the directory containing the config
+        let config_dir = fs::canonicalize(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let root = match config.settings.root {
+            Some(ref root) => config_dir.join(expand_home(root)),
+            None => config_dir,
+        };
+        for repo in config.repos.values_mut() {
+            repo.base = Some(root.clone());
         }
+        config.root = Some(root);
+
+        Ok(config)
+    }
+
+    /// Takes an exclusive advisory lock on the config at `path`, waiting for any
+    /// other process holding it. Hold this around loading, modifying and saving
+    /// the config so concurrent invocations can't lose each other's changes.
+    pub fn lock(path: &Path) -> Result<ConfigLock> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // lock the file that is saved to, however it is reached
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
 
-        let mut file = File::open(path)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(with_suffix(&path, ".lock"))?;
+        file.lock_exclusive()?;
 
-        match serde_json::from_str(&content) {
-            Ok(config) => Ok(config),
-            Err(err) => Err(err.into()),
+        Ok(ConfigLock { _file: file })
+    }
+
+    /// Parses the content of the config at `path`, in the format its extension
+    /// names, migrating it to the current schema version if necessary. Also
+    /// returns the schema version of the original content.
+    fn parse(content: &str, path: &Path) -> Result<(Self, u32)> {
+        let mut value = Format::from_path(path).parse(content)?;
+
+        let version = match value.get("version") {
+            Some(v) => v
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| RepoRsError::InvalidConfigVersion(v.to_string()))?,
+            None => 0,
+        };
+
+        if version > CONFIG_VERSION {
+            return Err(RepoRsError::InvalidConfigVersion(version.to_string()));
+        }
+
+        for migration in &MIGRATIONS[version as usize..] {
+            value = migration(value)?;
         }
+
+        // every format is checked against the schema as JSON, whose positions
+        // would be meaningless, so only the problem itself is reported
+        let config = serde_json::from_value(value)
+            .map_err(|e| RepoRsError::InvalidConfig(path.display().to_string(), e.to_string()))?;
+        Ok((config, version))
     }
 
+    /// All tracked repos and their keys, ordered by key
     pub fn repos_sorted(&self) -> Vec<(&String, &Repo)> {
         let mut ordered = self.repos.iter().collect::<Vec<_>>();
 
@@ -44,34 +287,471 @@ impl Config {
         ordered
     }
 
-    pub fn list(&self) {
-        let mut table = Table::new();
-        let format = format::FormatBuilder::new().padding(1, 1).build();
+    /// Drops all repos without at least one of the given tags. Does nothing if
+    /// `tags` is empty.
+    pub fn retain_tagged(&mut self, tags: &[String]) {
+        if !tags.is_empty() {
+            self.repos.retain(|_, repo| repo.has_any_tag(tags));
+        }
+    }
 
-        table.set_format(format);
-        for (_, repo) in self.repos_sorted() {
-            table.add_row(row![FW->&repo.key, &repo.path]);
+    /// Tracks a new repo. Fails rather than replacing a repo that is already
+    /// tracked under the same key.
+    pub fn add(&mut self, mut candidate: Repo) -> Result<()> {
+        if self.repos.contains_key(&candidate.key) {
+            return Err(RepoRsError::KeyExists(candidate.key));
         }
 
-        table.print_tty(true).expect("Failed to render table");
+        candidate.base = self.root.clone();
+        self.repos.insert(candidate.key.clone(), candidate);
+        Ok(())
     }
 
-    pub fn add(&mut self, candidate: Repo) {
-        self.repos.insert(candidate.key.clone(), candidate);
+    /// Finds the key of the repo identified by `key`, which may be any prefix
+    /// of a key as long as it matches only one repo
+    pub fn resolve(&self, key: &str) -> Result<String> {
+        if self.repos.contains_key(key) {
+            return Ok(key.to_string());
+        }
+
+        let mut matches: Vec<_> = self
+            .repos
+            .keys()
+            .filter(|k| k.starts_with(key))
+            .cloned()
+            .collect();
+        matches.sort();
+
+        match matches.len() {
+            0 => Err(RepoRsError::UnknownKey(key.to_string())),
+            1 => Ok(matches.remove(0)),
+            _ => Err(RepoRsError::AmbiguousKey(key.to_string(), matches)),
+        }
+    }
+
+    /// Finds the keys of all repos matching any of `patterns`, each of which
+    /// is either a glob or a key that is resolved as by `resolve`. Fails if a
+    /// pattern matches nothing.
+    pub fn select(&self, patterns: &[&str]) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+
+        for &pattern in patterns {
+            if !pattern.contains(['*', '?', '[']) {
+                keys.push(self.resolve(pattern)?);
+                continue;
+            }
+
+            let glob =
+                Pattern::new(pattern).map_err(|_| RepoRsError::UnknownKey(pattern.into()))?;
+            let before = keys.len();
+            keys.extend(self.repos.keys().filter(|k| glob.matches(k)).cloned());
+            if keys.len() == before {
+                return Err(RepoRsError::UnknownKey(pattern.to_string()));
+            }
+        }
+
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Suggests an unused key for `repo`, preferring its `owner/name` key if
+    /// its remote has one and otherwise numbering its current key
+    pub fn suggest_key(&self, repo: &Repo) -> String {
+        let namespaced = repo.remote_url().ok().and_then(|url| namespaced_key(&url));
+
+        if let Some(key) = namespaced {
+            if !self.repos.contains_key(&key) {
+                return key;
+            }
+        }
+
+        (2..)
+            .map(|n| format!("{}-{}", repo.key, n))
+            .find(|k| !self.repos.contains_key(k))
+            .unwrap()
+    }
+
+    /// Expresses `path` relative to the workspace root if it is within it, or
+    /// else relative to the home directory if it is within that
+    pub fn relativize(&self, path: &Path) -> String {
+        if let Some(rel) = self.root.as_ref().and_then(|r| path.strip_prefix(r).ok()) {
+            return rel.to_string_lossy().into_owned();
+        }
+
+        if let Some(home) = dirs::home_dir() {
+            if let Ok(rel) = path.strip_prefix(home) {
+                return Path::new("~").join(rel).to_string_lossy().into_owned();
+            }
+        }
+
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Rewrites every absolute or `~`-based path under `old_root` to be under
+    /// `new_root` instead, including the workspace root itself. Repos with
+    /// paths relative to the workspace root move with it. Returns the
+    /// `(key, old path, new path)` of each relocated repo.
+    pub fn relocate(&mut self, old_root: &Path, new_root: &str) -> Vec<(String, String, String)> {
+        if let Some(root) = self.settings.root.as_deref() {
+            if let Some(relocated) = relocate_path(root, old_root, new_root) {
+                self.settings.root = Some(relocated);
+            }
+        }
+
+        let mut relocated = Vec::new();
+        for (key, repo) in self.repos.iter_mut() {
+            if let Some(path) = relocate_path(&repo.path, old_root, new_root) {
+                relocated.push((key.clone(), repo.path.clone(), path.clone()));
+                repo.path = path;
+            }
+        }
+        relocated.sort();
+
+        relocated
+    }
+
+    /// Replaces the repo tracked under `key`, moving it to a new key if its key
+    /// has changed. Nothing is changed if the new key is already in use.
+    pub fn update(&mut self, key: &str, repo: Repo) -> Result<()> {
+        if !self.repos.contains_key(key) {
+            return Err(RepoRsError::UnknownKey(key.to_string()));
+        }
+
+        if repo.key != key {
+            if self.repos.contains_key(&repo.key) {
+                return Err(RepoRsError::KeyExists(repo.key));
+            }
+            self.repos.remove(key);
+        }
+
+        self.repos.insert(repo.key.clone(), repo);
+        Ok(())
     }
 
-    pub fn contains(&self, candidate: &Repo) -> bool {
-        self.repos.values().any(|v| v == candidate)
+    /// Checks whether any tracked repo has the given working directory
+    pub fn tracks_workdir(&self, workdir: &Path) -> bool {
+        self.repos.values().any(|r| r.workdir() == workdir)
     }
 
+    /// Stops tracking the repo with the given key, returning whether it was
+    /// tracked
     pub fn remove(&mut self, key: &str) -> bool {
         self.repos.remove(key).is_some()
     }
 
-    pub fn save(&self, path: &Path) -> Result<()> {
-        let json = serde_json::to_vec_pretty(&self)?;
-        let mut file = File::create(path)?;
-        file.write_all(&json)?;
+    /// Saves the config by writing to a temporary file and renaming it over the
+    /// original, keeping the previous few versions as backups. Fails if the
+    /// file was changed by something else since it was loaded.
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        let content = Format::from_path(path).serialize(self)?;
+
+        // write through symlinks rather than replacing them
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        if path.exists() {
+            if let Some(expected) = self.fingerprint {
+                if fingerprint(&fs::read(&path)?) != expected {
+                    return Err(RepoRsError::ConfigModified(path.display().to_string()));
+                }
+            }
+            // keep the original of a migrated config, which older versions of
+            // repo-rs can still read
+            if let Some(ref migrated) = self.migrated {
+                if !migrated.backup.exists() {
+                    fs::copy(&path, &migrated.backup)?;
+                }
+            }
+            rotate_backups(&path)?;
+        }
+
+        // the config may hold tokens, so a new one is only readable by the
+        // user and an existing one keeps its permissions
+        let tmp = with_suffix(&path, ".tmp");
+        {
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            let mut file = options.open(&tmp)?;
+            if let Ok(metadata) = fs::metadata(&path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(&content)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+
+        self.fingerprint = Some(fingerprint(&content));
         Ok(())
     }
 }
+
+/// Moves `path` from under `old_root` to under `new_root`, if it is an
+/// absolute or `~`-based path under `old_root`
+fn relocate_path(path: &str, old_root: &Path, new_root: &str) -> Option<String> {
+    let expanded = expand_home(path);
+    if !expanded.is_absolute() {
+        return None;
+    }
+
+    let rest = expanded
+        .strip_prefix(expand_home(&old_root.to_string_lossy()))
+        .ok()?;
+    Some(
+        Path::new(new_root)
+            .join(rest)
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// The number of previous versions of the config kept when saving
+const BACKUPS: usize = 3;
+
+/// Shifts `config.bak.1` to `config.bak.2` and so on, dropping the oldest, then
+/// copies the current config to `config.bak.1`
+fn rotate_backups(path: &Path) -> Result<()> {
+    for n in (1..BACKUPS).rev() {
+        let from = with_suffix(path, &format!(".bak.{}", n));
+        if from.exists() {
+            fs::rename(&from, with_suffix(path, &format!(".bak.{}", n + 1)))?;
+        }
+    }
+
+    fs::copy(path, with_suffix(path, ".bak.1"))?;
+    Ok(())
+}
+
+/// Appends `suffix` to the file name of `path`, e.g. `config.toml.lock`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn fingerprint(content: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_workspace, relocate_path, with_suffix, Config, Format, CONFIG_VERSION, WORKSPACE_FILE,
+    };
+    use crate::error::RepoRsError;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn migrates_unversioned_config() {
+        let content = r#"{"repos": {"foo": {
+            "key": "foo", "path": "/src/foo", "remote": "origin", "branch": "main"
+        }}}"#;
+
+        let (config, version) = Config::parse(content, Path::new("config.json")).unwrap();
+
+        assert_eq!(0, version);
+        assert_eq!(CONFIG_VERSION, config.version);
+        assert_eq!("/src/foo", config.repos["foo"].path);
+    }
+
+    #[test]
+    fn migrates_only_when_saving() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let original = r#"{"repos": {}}"#;
+        fs::write(&path, original).unwrap();
+
+        let mut config = Config::new(&path).unwrap();
+        let backup = config.migrated.as_ref().unwrap().backup.clone();
+        assert_eq!(original, fs::read_to_string(&path).unwrap());
+        assert!(!backup.exists());
+
+        config.save(&path).unwrap();
+        assert_eq!(original, fs::read_to_string(&backup).unwrap());
+        let (_, version) = Config::parse(&fs::read_to_string(&path).unwrap(), &path).unwrap();
+        assert_eq!(CONFIG_VERSION, version);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions_when_saving() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "version = 1\n[repos]\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let mut config = Config::new(&path).unwrap();
+        config.save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(0o600, mode);
+
+        let new = dir.path().join("new.toml");
+        Config::default().save(&new).unwrap();
+        let mode = fs::metadata(&new).unwrap().permissions().mode() & 0o777;
+        assert_eq!(0o600, mode);
+    }
+
+    #[test]
+    fn rejects_newer_config() {
+        let content = format!("version = {}\n[repos]\n", CONFIG_VERSION + 1);
+
+        assert!(Config::parse(&content, Path::new("config.toml")).is_err());
+
+        let content = "version = 4294967297\n[repos]\n";
+        assert!(matches!(
+            Config::parse(content, Path::new("config.toml")),
+            Err(RepoRsError::InvalidConfigVersion(_))
+        ));
+    }
+
+    #[test]
+    fn explains_schema_errors() {
+        let path = Path::new("config.toml");
+        let (config, _) = Config::parse("[settings]\njobs = 2\n", path).unwrap();
+        assert_eq!(Some(2), config.settings.jobs);
+        assert!(config.repos.is_empty());
+
+        match Config::parse("[settings]\njobs = \"many\"\n", path) {
+            Err(e @ RepoRsError::InvalidConfig(_, _)) => {
+                let message = e.to_string();
+                assert!(message.starts_with("Invalid config file 'config.toml'"));
+                assert!(!message.contains("line 0"), "{}", message);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("an invalid config was accepted"),
+        }
+    }
+
+    #[test]
+    fn round_trips_all_formats() {
+        let content = r#"{"version": 1, "settings": {"jobs": 4, "tags": ["work"]}, "repos": {"foo": {
+            "key": "foo", "path": "/src/foo", "remote": "origin", "branch": "main", "timeout": 30
+        }}}"#;
+        let (config, _) = Config::parse(content, Path::new("config.json")).unwrap();
+
+        for name in ["config.json", "config.toml", "config.yaml"] {
+            let path = Path::new(name);
+            let serialized = Format::from_path(path).serialize(&config).unwrap();
+            let (parsed, version) =
+                Config::parse(std::str::from_utf8(&serialized).unwrap(), path).unwrap();
+
+            assert_eq!(CONFIG_VERSION, version);
+            assert_eq!(Some(30), parsed.repos["foo"].timeout);
+            assert_eq!(Some(4), parsed.settings.jobs);
+            assert_eq!(vec!["work"], parsed.settings.tags);
+        }
+    }
+
+    #[test]
+    fn finds_nearest_workspace() {
+        let root = tempfile::tempdir().unwrap();
+        let nested = root.path().join("team").join("project").join("src");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(None, find_workspace(&nested));
+
+        fs::write(root.path().join(WORKSPACE_FILE), "[repos]\n").unwrap();
+        assert_eq!(
+            Some(root.path().join(WORKSPACE_FILE)),
+            find_workspace(&nested)
+        );
+
+        let team = root.path().join("team");
+        fs::write(team.join(WORKSPACE_FILE), "[repos]\n").unwrap();
+        assert_eq!(Some(team.join(WORKSPACE_FILE)), find_workspace(&nested));
+    }
+
+    #[test]
+    fn relocates_paths_under_old_root() {
+        let old_root = Path::new("/home/me/src");
+
+        assert_eq!(
+            Some("/mnt/code/team/foo".to_string()),
+            relocate_path("/home/me/src/team/foo", old_root, "/mnt/code")
+        );
+        assert_eq!(
+            Some("~/code/foo".to_string()),
+            relocate_path("/home/me/src/foo", old_root, "~/code")
+        );
+        assert_eq!(
+            None,
+            relocate_path("/home/me/srcs/foo", old_root, "/mnt/code")
+        );
+        assert_eq!(None, relocate_path("team/foo", old_root, "/mnt/code"));
+    }
+
+    #[test]
+    fn save_detects_concurrent_modification() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let mut config = Config::new(&path).unwrap();
+        config.save(&path).unwrap();
+        assert!(with_suffix(&path, ".bak.1").exists());
+
+        let mut other = Config::new(&path).unwrap();
+        other.save(&path).unwrap();
+        fs::write(&path, "version = 1\n\n[settings]\njobs = 2\n\n[repos]\n").unwrap();
+
+        assert!(config.save(&path).is_err());
+        assert!(other.save(&path).is_err());
+        assert!(Config::new(&path).unwrap().save(&path).is_ok());
+    }
+
+    #[test]
+    fn renames_keys_without_clobbering() {
+        let content = r#"{"version": 1, "repos": {
+            "foo": {"key": "foo", "path": "/src/foo", "remote": "origin", "branch": "main"},
+            "bar": {"key": "bar", "path": "/src/bar", "remote": "origin", "branch": "main"}
+        }}"#;
+        let (mut config, _) = Config::parse(content, Path::new("config.json")).unwrap();
+
+        let mut foo = config.repos["foo"].clone();
+        foo.key = "bar".to_string();
+        assert!(config.update("foo", foo.clone()).is_err());
+        assert_eq!("/src/bar", config.repos["bar"].path);
+
+        foo.key = "baz".to_string();
+        config.update("foo", foo).unwrap();
+        assert!(!config.repos.contains_key("foo"));
+        assert_eq!("/src/foo", config.repos["baz"].path);
+    }
+
+    #[test]
+    fn resolves_unambiguous_prefixes() {
+        let content = r#"{"version": 1, "repos": {
+            "org/foo": {"key": "org/foo", "path": "/src/foo", "remote": "origin", "branch": "main"},
+            "org/foobar": {"key": "org/foobar", "path": "/src/foobar", "remote": "origin", "branch": "main"},
+            "other/baz": {"key": "other/baz", "path": "/src/baz", "remote": "origin", "branch": "main"}
+        }}"#;
+        let (config, _) = Config::parse(content, Path::new("config.json")).unwrap();
+
+        assert_eq!("org/foo", config.resolve("org/foo").unwrap());
+        assert_eq!("other/baz", config.resolve("ot").unwrap());
+        assert!(config.resolve("org/f").is_err());
+        assert!(config.resolve("nope").is_err());
+    }
+
+    #[test]
+    fn selects_keys_by_glob_and_prefix() {
+        let content = r#"{"version": 1, "repos": {
+            "org/foo": {"key": "org/foo", "path": "/src/foo", "remote": "origin", "branch": "main"},
+            "org/bar": {"key": "org/bar", "path": "/src/bar", "remote": "origin", "branch": "main"},
+            "other/baz": {"key": "other/baz", "path": "/src/baz", "remote": "origin", "branch": "main"}
+        }}"#;
+        let (config, _) = Config::parse(content, Path::new("config.json")).unwrap();
+
+        assert_eq!(
+            vec!["org/bar", "org/foo", "other/baz"],
+            config.select(&["org/*", "ot"]).unwrap()
+        );
+        assert!(config.select(&["org/*", "nope/*"]).is_err());
+    }
+}
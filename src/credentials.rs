@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use git2::{Cred, CredentialType, ErrorClass, ErrorCode};
+
+use crate::repo::expand_home;
+
+/// The key files tried when no other key is configured for a host, in order
+const DEFAULT_KEYS: &[&str] = &["~/.ssh/id_ed25519", "~/.ssh/id_ecdsa", "~/.ssh/id_rsa"];
+
+/// How to authenticate when fetching from remotes. Nothing here ever prompts
+/// directly; in batch mode, credential helpers are not allowed to prompt
+/// either.
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    /// Fail instead of waiting for a passphrase, password or confirmation
+    pub batch: bool,
+
+    /// SSH key files to try for each host, which may start with `~`
+    pub host_keys: BTreeMap<String, String>,
+}
+
+/// The credentials offered while connecting to a single remote. libgit2 asks
+/// again each time a credential is rejected, so every one is offered at most
+/// once to make sure the fetch fails instead of looping forever.
+pub(crate) struct Attempts<'a> {
+    auth: &'a Auth,
+    url: String,
+    keys: Vec<PathBuf>,
+    agent: bool,
+    helper: bool,
+    default: bool,
+    filled: Option<(String, String)>,
+    /// Descriptions of everything that was offered, in order
+    pub tried: Vec<String>,
+}
+
+impl<'a> Attempts<'a> {
+    /// Starts offering credentials for `url`, trying the repo's own key file
+    /// and then those configured for its host before the defaults
+    pub fn new(auth: &'a Auth, url: &str, repo_key: Option<&str>) -> Attempts<'a> {
+        let host_key = host(url).and_then(|h| auth.host_keys.get(&h));
+        let keys = repo_key
+            .into_iter()
+            .chain(host_key.map(String::as_str))
+            .map(expand_home)
+            .collect();
+
+        Attempts {
+            auth,
+            url: url.to_string(),
+            keys,
+            agent: false,
+            helper: false,
+            default: false,
+            filled: None,
+            tried: Vec::new(),
+        }
+    }
+
+    /// The next credential to offer, or an error once there are none left
+    pub fn next(
+        &mut self,
+        username: Option<&str>,
+        allowed: CredentialType,
+    ) -> Result<Cred, git2::Error> {
+        let username = username.unwrap_or("git");
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if let Some(cred) = self.next_ssh_key(username) {
+                return Ok(cred);
+            }
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !self.helper {
+            self.helper = true;
+            self.tried.push("git credential helpers".to_string());
+            if let Some((username, password)) = self.fill() {
+                let cred = Cred::userpass_plaintext(&username, &password);
+                self.filled = Some((username, password));
+                return cred;
+            }
+        }
+
+        if allowed.contains(CredentialType::DEFAULT) && !self.default {
+            self.default = true;
+            return Cred::default();
+        }
+
+        Err(git2::Error::new(
+            ErrorCode::Auth,
+            ErrorClass::Callback,
+            "no more credentials to try",
+        ))
+    }
+
+    fn next_ssh_key(&mut self, username: &str) -> Option<Cred> {
+        // explicitly configured keys come first, since the agent may hold so
+        // many keys that the server gives up before the right one is offered
+        while !self.keys.is_empty() {
+            let key = self.keys.remove(0);
+            if !key.is_file() {
+                self.tried
+                    .push(format!("key file '{}' (not found)", key.display()));
+                continue;
+            }
+            self.tried.push(format!("key file '{}'", key.display()));
+            if let Ok(cred) = Cred::ssh_key(username, None, &key, None) {
+                return Some(cred);
+            }
+        }
+
+        if !self.agent {
+            self.agent = true;
+            self.keys = DEFAULT_KEYS
+                .iter()
+                .map(|k| expand_home(k))
+                .filter(|k| k.is_file())
+                .collect();
+
+            if env::var_os("SSH_AUTH_SOCK").is_some() {
+                self.tried.push("ssh-agent".to_string());
+                if let Ok(cred) = Cred::ssh_key_from_agent(username) {
+                    return Some(cred);
+                }
+            }
+            return self.next_ssh_key(username);
+        }
+
+        None
+    }
+
+    /// Asks the configured git credential helpers for a username and password
+    fn fill(&self) -> Option<(String, String)> {
+        let output = self.credential("fill", &[])?;
+        let mut username = None;
+        let mut password = None;
+
+        for line in String::from_utf8_lossy(&output).lines() {
+            match line.split_once('=') {
+                Some(("username", value)) => username = Some(value.to_string()),
+                Some(("password", value)) => password = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Some((username?, password?))
+    }
+
+    /// Tells the credential helpers whether the password they supplied worked,
+    /// so they can store or forget it
+    pub fn finish(&self, succeeded: bool) {
+        if let Some((ref username, ref password)) = self.filled {
+            let action = match succeeded {
+                true => "approve",
+                false => "reject",
+            };
+            let fields = [("username", username.as_str()), ("password", password)];
+            // helpers that can't store credentials are not worth failing for
+            let _ = self.credential(action, &fields);
+        }
+    }
+
+    /// Runs `git credential <action>`, returning its output if it succeeded
+    fn credential(&self, action: &str, fields: &[(&str, &str)]) -> Option<Vec<u8>> {
+        let mut cmd = Command::new("git");
+        cmd.args(["credential", action])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        if self.auth.batch {
+            cmd.env("GIT_TERMINAL_PROMPT", "0")
+                .env("GCM_INTERACTIVE", "never");
+        }
+
+        let mut child = cmd.spawn().ok()?;
+        let mut input = format!("url={}\n", self.url);
+        for (name, value) in fields {
+            input.push_str(&format!("{}={}\n", name, value));
+        }
+        input.push('\n');
+        child.stdin.take()?.write_all(input.as_bytes()).ok()?;
+
+        let output = child.wait_with_output().ok()?;
+        match output.status.success() {
+            true => Some(output.stdout),
+            false => None,
+        }
+    }
+}
+
+/// The host name in a remote URL, in either URL or scp-like
+/// `user@host:owner/name.git` form
+pub fn host(url: &str) -> Option<String> {
+    match url::Url::parse(url) {
+        Ok(parsed) if parsed.has_host() => parsed.host_str().map(String::from),
+        _ => {
+            let (authority, _) = url.split_once(':')?;
+            let host = authority.rsplit('@').next()?;
+            Some(host.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host, Attempts, Auth};
+    use git2::CredentialType;
+    use std::path::PathBuf;
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            Some("github.com".to_string()),
+            host("git@github.com:a/b.git")
+        );
+        assert_eq!(
+            Some("gitlab.com".to_string()),
+            host("ssh://git@gitlab.com:22/a/b")
+        );
+        assert_eq!(
+            Some("example.com".to_string()),
+            host("https://example.com/a/b")
+        );
+        assert_eq!(None, host("/srv/git/a.git"));
+    }
+
+    #[test]
+    fn offers_each_key_once() {
+        let mut auth = Auth::default();
+        auth.host_keys
+            .insert("github.com".to_string(), "/nonexistent/host".to_string());
+        let mut attempts =
+            Attempts::new(&auth, "git@github.com:a/b.git", Some("/nonexistent/repo"));
+        assert_eq!(
+            vec![
+                PathBuf::from("/nonexistent/repo"),
+                PathBuf::from("/nonexistent/host")
+            ],
+            attempts.keys
+        );
+
+        // if every key is rejected, the attempts eventually run out
+        let mut offered = 0;
+        while attempts.next(None, CredentialType::SSH_KEY).is_ok() {
+            offered += 1;
+            assert!(offered < 10, "{:?}", attempts.tried);
+        }
+        assert_eq!(
+            "key file '/nonexistent/repo' (not found)",
+            attempts.tried[0]
+        );
+        assert_eq!(
+            "key file '/nonexistent/host' (not found)",
+            attempts.tried[1]
+        );
+    }
+}
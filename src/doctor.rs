@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use git2::BranchType;
+
+use crate::config::Config;
+use crate::repo::Repo;
+
+/// A problem with a tracked repo's entry in the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The entry's key differs from the key it is tracked under
+    KeyMismatch(String),
+
+    /// The repo's path does not exist
+    MissingPath,
+
+    /// The repo's path is not the working directory of a git repository
+    NotARepo,
+
+    /// The tracked remote does not exist. Contains the first remote that does,
+    /// if any.
+    MissingRemote(Option<String>),
+
+    /// The tracked branch exists neither locally nor on the remote. Contains
+    /// the currently checked out branch, if it can be determined.
+    MissingBranch(Option<String>),
+
+    /// The tracked branch only exists on the remote
+    BranchOnlyOnRemote,
+
+    /// The repo has the same path as the entry with the given key
+    DuplicatePath(String),
+}
+
+impl Issue {
+    /// Warnings don't prevent any commands from working
+    pub fn is_warning(&self) -> bool {
+        matches!(*self, Issue::BranchOnlyOnRemote)
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::KeyMismatch(ref key) => write!(f, "entry has mismatched key '{}'", key),
+            Issue::MissingPath => write!(f, "path does not exist"),
+            Issue::NotARepo => write!(f, "path is not a git repository"),
+            Issue::MissingRemote(_) => write!(f, "tracked remote does not exist"),
+            Issue::MissingBranch(_) => {
+                write!(f, "tracked branch does not exist locally or on the remote")
+            }
+            Issue::BranchOnlyOnRemote => write!(f, "tracked branch only exists on the remote"),
+            Issue::DuplicatePath(ref other) => write!(f, "has the same path as '{}'", other),
+        }
+    }
+}
+
+/// An issue found with the repo tracked under `key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub key: String,
+    pub issue: Issue,
+}
+
+impl Diagnosis {
+    fn new(key: &str, issue: Issue) -> Diagnosis {
+        Diagnosis {
+            key: key.to_string(),
+            issue,
+        }
+    }
+
+    /// Attempts to repair the issue, untracking the repo if it can't be
+    /// repaired in place. Returns a description of what was done, or `None` if
+    /// nothing could be done.
+    pub fn fix(&self, config: &mut Config) -> Option<String> {
+        let repo = config.repos.get_mut(&self.key)?;
+
+        match self.issue {
+            Issue::KeyMismatch(_) => {
+                repo.key = self.key.clone();
+                Some(format!("set key to '{}'", self.key))
+            }
+            Issue::MissingPath | Issue::NotARepo | Issue::DuplicatePath(_) => {
+                config.remove(&self.key);
+                Some("untracked".to_string())
+            }
+            Issue::MissingRemote(Some(ref remote)) => {
+                repo.remote = remote.clone();
+                Some(format!("set remote to '{}'", remote))
+            }
+            Issue::MissingBranch(Some(ref branch)) => {
+                repo.branch = branch.clone();
+                Some(format!("set branch to '{}'", branch))
+            }
+            Issue::MissingRemote(None) | Issue::MissingBranch(None) | Issue::BranchOnlyOnRemote => {
+                None
+            }
+        }
+    }
+}
+
+/// Checks every tracked repo for problems with its config entry
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    let mut diagnoses = Vec::new();
+    let mut paths = HashMap::new();
+
+    for (key, repo) in config.repos_sorted() {
+        if &repo.key != key {
+            diagnoses.push(Diagnosis::new(key, Issue::KeyMismatch(repo.key.clone())));
+        }
+
+        let workdir = repo.workdir();
+        if !workdir.exists() {
+            diagnoses.push(Diagnosis::new(key, Issue::MissingPath));
+            continue;
+        }
+
+        let canonical = fs::canonicalize(&workdir).unwrap_or(workdir);
+        if let Some(other) = paths.insert(canonical, key.clone()) {
+            diagnoses.push(Diagnosis::new(key, Issue::DuplicatePath(other)));
+            continue;
+        }
+
+        diagnoses.extend(
+            diagnose_repository(repo)
+                .into_iter()
+                .map(|issue| Diagnosis::new(key, issue)),
+        );
+    }
+
+    diagnoses
+}
+
+fn diagnose_repository(repo: &Repo) -> Vec<Issue> {
+    let repository = match repo.repository() {
+        Ok(repository) => repository,
+        Err(_) => return vec![Issue::NotARepo],
+    };
+
+    let mut issues = Vec::new();
+
+    let remote_exists = repository.find_remote(&repo.remote).is_ok();
+    if !remote_exists {
+        let candidate = repository
+            .remotes()
+            .ok()
+            .and_then(|remotes| remotes.get(0).map(|r| r.to_string()));
+        issues.push(Issue::MissingRemote(candidate));
+    }
+
+    let local = repository
+        .find_branch(&repo.branch, BranchType::Local)
+        .is_ok();
+    let remote = remote_exists
+        && repository
+            .find_branch(
+                &format!("{}/{}", repo.remote, repo.branch),
+                BranchType::Remote,
+            )
+            .is_ok();
+
+    match (local, remote) {
+        (false, false) => issues.push(Issue::MissingBranch(repo.current_branch().ok())),
+        (false, true) => issues.push(Issue::BranchOnlyOnRemote),
+        _ => {}
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diagnose, Diagnosis, Issue};
+    use crate::config::Config;
+    use crate::repo::Repo;
+    use git2::{Repository, Signature};
+    use std::path::Path;
+
+    fn init_repo(path: &Path) {
+        let repository = Repository::init(path).unwrap();
+        repository
+            .remote("upstream", "https://example.com/foo.git")
+            .unwrap();
+
+        let tree = repository
+            .find_tree(repository.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        repository
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+    }
+
+    fn track(config: &mut Config, key: &str, path: &Path) {
+        let repo = Repo {
+            key: key.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            remote: "origin".to_string(),
+            branch: "nope".to_string(),
+            ..Default::default()
+        };
+        config.repos.insert(key.to_string(), repo);
+    }
+
+    #[test]
+    fn diagnoses_and_fixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_path = dir.path().join("foo");
+        init_repo(&repo_path);
+        let branch = Repository::open(&repo_path)
+            .unwrap()
+            .head()
+            .unwrap()
+            .shorthand()
+            .unwrap()
+            .to_string();
+
+        let mut config = Config::default();
+        track(&mut config, "a", &repo_path);
+        track(&mut config, "b", &repo_path);
+        track(&mut config, "c", &dir.path().join("missing"));
+        config.repos.get_mut("c").unwrap().key = "d".to_string();
+        // a directory inside a repository is not a repository itself
+        let nested = repo_path.join("nested");
+        std::fs::create_dir(&nested).unwrap();
+        track(&mut config, "e", &nested);
+
+        let diagnoses = diagnose(&config);
+        assert_eq!(
+            vec![
+                Diagnosis::new("a", Issue::MissingRemote(Some("upstream".to_string()))),
+                Diagnosis::new("a", Issue::MissingBranch(Some(branch.clone()))),
+                Diagnosis::new("b", Issue::DuplicatePath("a".to_string())),
+                Diagnosis::new("c", Issue::KeyMismatch("d".to_string())),
+                Diagnosis::new("c", Issue::MissingPath),
+                Diagnosis::new("e", Issue::NotARepo),
+            ],
+            diagnoses
+        );
+
+        for diagnosis in &diagnoses {
+            assert!(diagnosis.fix(&mut config).is_some());
+        }
+
+        assert!(diagnose(&config).is_empty());
+        assert_eq!(vec!["a"], config.repos.keys().collect::<Vec<_>>());
+        assert_eq!("upstream", config.repos["a"].remote);
+        assert_eq!(branch, config.repos["a"].branch);
+    }
+}
@@ -1,15 +1,61 @@
-use std::{env, fmt::Display};
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
 
+use serde_derive::Serialize;
 use serde_json::error::Error as SerdeError;
 
+/// The number of trailing lines of a failed command's output that are kept
+/// for reporting
+const TAIL_LINES: usize = 10;
+
 /// RepoRsError enumerates all possible errors returned by this library
 #[derive(Debug)]
 pub enum RepoRsError {
     /// Represents a failure to determine the current branch
     BranchUnknown(String),
 
+    /// Represents a key prefix that matches more than one tracked repo
+    AmbiguousKey(String, Vec<String>),
+
+    /// Represents a branch that exists neither locally nor on the tracked
+    /// remote of a repository
+    BranchNotFound(String, String),
+
+    /// Represents an unsuccessful response from a hosting service's API
+    ApiError(String, u16, String),
+
+    /// Represents a remote that rejected every credential that was offered
+    AuthFailed(String, String, Vec<String>),
+
+    /// Represents a remote whose host key or certificate could not be verified
+    CertificateRejected(String, String),
+
+    /// Represents a config file that was changed by something else since it
+    /// was loaded
+    ConfigModified(String),
+
+    /// Represents a config file whose schema version is not a number or is
+    /// newer than this version of repo-rs supports. A config without a version
+    /// is read as version 0.
+    InvalidConfigVersion(String),
+
+    /// Represents a config file that doesn't match the schema, with the path
+    /// of the file and the problem
+    InvalidConfig(String, String),
+
     /// Represents a command returning a nonzero exit code
-    CommandFailed(String, tokio::process::Command, std::process::Output),
+    CommandFailed(String, Box<CommandFailure>, Box<Output>),
+
+    /// Represents a URL that could not be parsed
+    InvalidUrl(String),
+
+    /// Represents a key that is already used by another tracked repo
+    KeyExists(String),
+
+    /// Represents a forge with no public instance whose API URL was not given
+    NoApiUrl(String),
 
     /// Represents a repository with no remotes defined
     NoRemotes(String),
@@ -23,11 +69,34 @@ pub enum RepoRsError {
     /// Represents repository that is dirty
     RepoDirty(String),
 
+    /// Represents a remote that is not defined in a repository
+    RemoteNotFound(String, String),
+
+    /// Represents a task for a repository that panicked
+    Panicked(String),
+
+    /// Represents a forge whose API rate limit is exhausted for longer than is
+    /// worth waiting, along with a hint on providing a token if none was used
+    RateLimited(String, std::time::Duration, Option<String>),
+
+    /// Represents a command that was killed for exceeding its timeout
+    Timeout(String, String, std::time::Duration),
+
+    /// Represents a repository whose working tree would lose work if deleted
+    UnpushedWork(String, String),
+
+    /// Represents a forge that requires a token, or rejected the one found in
+    /// the given place, along with a hint on how to provide one
+    Unauthorized(String, Option<String>, String),
+
+    /// Represents a key that no tracked repo uses
+    UnknownKey(String),
+
     /// Represents all other cases of `git2::Error`
     GitError(git2::Error),
 
-    /// Represents all other cases of `github_v3::GHError`
-    GithubError(github_v3::GHError),
+    /// Represents all other cases of `reqwest::Error`
+    HttpError(reqwest::Error),
 
     /// Represents all other cases of `std::io::Error`
     IOError(std::io::Error),
@@ -35,38 +104,206 @@ pub enum RepoRsError {
     /// Represents all other cases of `serde_json::error::Error`
     JsonError(SerdeError),
 
+    /// Represents all other cases of `toml::de::Error`
+    TomlError(toml::de::Error),
+
+    /// Represents all other cases of `toml::ser::Error`
+    TomlSerializeError(toml::ser::Error),
+
     /// Represents all other cases of `env::VarError`
     VarError(env::VarError),
+
+    /// Represents all other cases of `serde_yaml::Error`
+    YamlError(serde_yaml::Error),
+}
+
+/// The details of a command that exited unsuccessfully, in a form that can be
+/// displayed or serialized
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommandFailure {
+    /// The command line, with arguments containing whitespace quoted
+    pub command: String,
+
+    /// The directory the command ran in
+    pub cwd: PathBuf,
+
+    /// The exit code, if the command exited normally
+    pub code: Option<i32>,
+
+    /// The signal that terminated the command, if any
+    pub signal: Option<i32>,
+
+    /// The last lines of stdout, decoded as UTF-8
+    pub stdout: String,
+
+    /// The last lines of stderr, decoded as UTF-8
+    pub stderr: String,
+}
+
+impl CommandFailure {
+    pub fn new(command: String, cwd: PathBuf, output: &Output) -> CommandFailure {
+        CommandFailure {
+            command,
+            cwd,
+            code: output.status.code(),
+            signal: signal(&output.status),
+            stdout: tail(&output.stdout),
+            stderr: tail(&output.stderr),
+        }
+    }
+
+    /// Describes how the command ended, e.g. "exited with code 1"
+    pub fn describe_status(&self) -> String {
+        match (self.code, self.signal) {
+            (Some(code), _) => format!("exited with code {}", code),
+            (None, Some(signal)) => format!("was terminated by signal {}", signal),
+            (None, None) => "was terminated".to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The last `TAIL_LINES` lines of `bytes`, decoded as UTF-8
+fn tail(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let lines = text.trim_end().lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(TAIL_LINES)..].join("\n")
 }
 
 impl std::error::Error for RepoRsError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             RepoRsError::BranchUnknown(_) => None,
+            RepoRsError::AmbiguousKey(_, _) => None,
+            RepoRsError::BranchNotFound(_, _) => None,
+            RepoRsError::ApiError(_, _, _) => None,
+            RepoRsError::AuthFailed(_, _, _) => None,
+            RepoRsError::CertificateRejected(_, _) => None,
+            RepoRsError::ConfigModified(_) => None,
+            RepoRsError::InvalidConfigVersion(_) => None,
+            RepoRsError::InvalidConfig(_, _) => None,
             RepoRsError::CommandFailed(_, _, _) => None,
+            RepoRsError::InvalidUrl(_) => None,
+            RepoRsError::KeyExists(_) => None,
+            RepoRsError::NoApiUrl(_) => None,
             RepoRsError::NoRemotes(_) => None,
             RepoRsError::NoRepo(_) => None,
             RepoRsError::OperationsInProgress(_) => None,
             RepoRsError::RepoDirty(_) => None,
+            RepoRsError::RemoteNotFound(_, _) => None,
+            RepoRsError::Panicked(_) => None,
+            RepoRsError::RateLimited(_, _, _) => None,
+            RepoRsError::Timeout(_, _, _) => None,
+            RepoRsError::Unauthorized(_, _, _) => None,
+            RepoRsError::UnknownKey(_) => None,
+            RepoRsError::UnpushedWork(_, _) => None,
             RepoRsError::GitError(ref err) => Some(err),
-            RepoRsError::GithubError(ref err) => Some(err),
+            RepoRsError::HttpError(ref err) => Some(err),
             RepoRsError::IOError(ref err) => Some(err),
             RepoRsError::JsonError(ref err) => Some(err),
+            RepoRsError::TomlError(ref err) => Some(err),
+            RepoRsError::TomlSerializeError(ref err) => Some(err),
             RepoRsError::VarError(ref err) => Some(err),
+            RepoRsError::YamlError(ref err) => Some(err),
         }
     }
 }
 
-impl std::fmt::Display for RepoRsError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl fmt::Display for RepoRsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             RepoRsError::BranchUnknown(ref key) => {
                 write!(f, "Could not determine current branch for '{}'", key)
             }
-            RepoRsError::CommandFailed(ref key, ref command, ref output) => write!(
+            RepoRsError::AmbiguousKey(ref prefix, ref keys) => write!(
+                f,
+                "'{}' matches more than one repo: {}",
+                prefix,
+                keys.join(", ")
+            ),
+            RepoRsError::BranchNotFound(ref key, ref branch) => write!(
+                f,
+                "Branch '{}' does not exist locally or on the remote of '{}'",
+                branch, key
+            ),
+            RepoRsError::ApiError(ref url, status, ref message) => {
+                write!(
+                    f,
+                    "Request to '{}' failed with {}: {}",
+                    url, status, message
+                )
+            }
+            RepoRsError::AuthFailed(ref key, ref url, ref tried) => {
+                write!(f, "Could not authenticate with '{}' for '{}'", url, key)?;
+                match tried.is_empty() {
+                    true => write!(f, ": no credentials were available")?,
+                    false => write!(f, " after trying {}", tried.join(", "))?,
+                }
+                write!(
+                    f,
+                    ". Add a key to ssh-agent, set `ssh_key` for the repo or configure a git \
+                     credential helper"
+                )
+            }
+            RepoRsError::CertificateRejected(ref key, ref url) => write!(
                 f,
-                "Error running `{:?}` in '{}': {:?}",
-                command, key, output
+                "Could not verify the identity of '{}' for '{}'. For SSH remotes, connect once \
+                 with `ssh` to add the host key to known_hosts",
+                url, key
+            ),
+            RepoRsError::ConfigModified(ref path) => write!(
+                f,
+                "Config file '{}' was modified by another process. Please try again",
+                path
+            ),
+            RepoRsError::InvalidConfigVersion(ref version) => write!(
+                f,
+                "Unsupported config version {}. The newest supported version is {}",
+                version,
+                crate::config::CONFIG_VERSION
+            ),
+            RepoRsError::InvalidConfig(ref path, ref problem) => {
+                write!(f, "Invalid config file '{}': {}", path, problem)
+            }
+            RepoRsError::CommandFailed(ref key, ref failure, _) => {
+                write!(
+                    f,
+                    "`{}` {} in '{}' ({})",
+                    failure.command,
+                    failure.describe_status(),
+                    key,
+                    failure.cwd.display()
+                )?;
+                // stderr usually explains the failure, but some commands only
+                // write to stdout
+                let tail = match failure.stderr.is_empty() {
+                    true => &failure.stdout,
+                    false => &failure.stderr,
+                };
+                for line in tail.lines() {
+                    write!(f, "\n    | {}", line)?;
+                }
+                Ok(())
+            }
+            RepoRsError::InvalidUrl(ref url) => write!(f, "'{}' is not a valid URL", url),
+            RepoRsError::KeyExists(ref key) => {
+                write!(f, "A repo is already being tracked as '{}'", key)
+            }
+            RepoRsError::NoApiUrl(ref forge) => write!(
+                f,
+                "{} has no public instance, so an API URL must be given with --api-url or in \
+                 the forge's settings",
+                forge
             ),
             RepoRsError::NoRemotes(ref key) => write!(
                 f,
@@ -88,11 +325,57 @@ impl std::fmt::Display for RepoRsError {
                 "Repository '{}' is dirty. Maybe attempt with --stash option?",
                 key
             ),
+            RepoRsError::RemoteNotFound(ref key, ref remote) => {
+                write!(f, "Remote '{}' does not exist in '{}'", remote, key)
+            }
+            RepoRsError::Panicked(ref key) => {
+                write!(f, "Unexpected internal error while processing '{}'", key)
+            }
+            RepoRsError::RateLimited(ref host, ref wait, ref hint) => {
+                let minutes = wait.as_secs().div_ceil(60);
+                write!(
+                    f,
+                    "The API rate limit of {} is exhausted until it resets in {} minute{}",
+                    host,
+                    minutes,
+                    if minutes == 1 { "" } else { "s" }
+                )?;
+                match hint {
+                    Some(hint) => write!(
+                        f,
+                        ". Requests without a token have a much lower limit. {}",
+                        hint
+                    ),
+                    None => Ok(()),
+                }
+            }
+            RepoRsError::Timeout(ref key, ref command, ref duration) => write!(
+                f,
+                "Timed out running `{}` in '{}' after {}s",
+                command,
+                key,
+                duration.as_secs()
+            ),
+            RepoRsError::Unauthorized(ref host, ref source, ref hint) => match source {
+                Some(source) => write!(f, "{} rejected the token from {}. {}", host, source, hint),
+                None => write!(
+                    f,
+                    "{} requires authentication, but no token was found. {}",
+                    host, hint
+                ),
+            },
+            RepoRsError::UnknownKey(ref key) => write!(f, "No repo is tracked as '{}'", key),
+            RepoRsError::UnpushedWork(ref key, ref reason) => {
+                write!(f, "Not deleting '{}', which {}", key, reason)
+            }
             RepoRsError::GitError(ref err) => err.fmt(f),
-            RepoRsError::GithubError(ref err) => err.fmt(f),
+            RepoRsError::HttpError(ref err) => err.fmt(f),
             RepoRsError::IOError(ref err) => err.fmt(f),
             RepoRsError::JsonError(ref err) => err.fmt(f),
+            RepoRsError::TomlError(ref err) => err.fmt(f),
+            RepoRsError::TomlSerializeError(ref err) => err.fmt(f),
             RepoRsError::VarError(ref err) => err.fmt(f),
+            RepoRsError::YamlError(ref err) => err.fmt(f),
         }
     }
 }
@@ -103,9 +386,9 @@ impl From<git2::Error> for RepoRsError {
     }
 }
 
-impl From<github_v3::GHError> for RepoRsError {
-    fn from(err: github_v3::GHError) -> RepoRsError {
-        RepoRsError::GithubError(err)
+impl From<reqwest::Error> for RepoRsError {
+    fn from(err: reqwest::Error) -> RepoRsError {
+        RepoRsError::HttpError(err)
     }
 }
 
@@ -121,55 +404,59 @@ impl From<SerdeError> for RepoRsError {
     }
 }
 
-impl From<env::VarError> for RepoRsError {
-    fn from(err: env::VarError) -> RepoRsError {
-        RepoRsError::VarError(err)
+impl From<toml::de::Error> for RepoRsError {
+    fn from(err: toml::de::Error) -> RepoRsError {
+        RepoRsError::TomlError(err)
     }
 }
 
-pub type Result<T> = std::result::Result<T, RepoRsError>;
-
-pub trait UnwrapOrExit<T>
-where
-    Self: Sized,
-{
-    fn unwrap_or_else<F>(self, f: F) -> T
-    where
-        F: FnOnce() -> T;
-
-    fn unwrap_or_exit(self, message: &str) -> T {
-        let err = clap::Error::with_description(message, clap::ErrorKind::InvalidValue);
-        self.unwrap_or_else(|| err.exit())
+impl From<toml::ser::Error> for RepoRsError {
+    fn from(err: toml::ser::Error) -> RepoRsError {
+        RepoRsError::TomlSerializeError(err)
     }
 }
 
-impl<T> UnwrapOrExit<T> for Option<T> {
-    fn unwrap_or_else<F>(self, f: F) -> T
-    where
-        F: FnOnce() -> T,
-    {
-        self.unwrap_or_else(f)
+impl From<serde_yaml::Error> for RepoRsError {
+    fn from(err: serde_yaml::Error) -> RepoRsError {
+        RepoRsError::YamlError(err)
     }
 }
 
-impl<T, E> UnwrapOrExit<T> for std::result::Result<T, E>
-where
-    E: Display,
-{
-    fn unwrap_or_else<F>(self, f: F) -> T
-    where
-        F: FnOnce() -> T,
-    {
-        self.unwrap_or_else(|_| f())
+impl From<env::VarError> for RepoRsError {
+    fn from(err: env::VarError) -> RepoRsError {
+        RepoRsError::VarError(err)
     }
+}
+
+pub type Result<T> = std::result::Result<T, RepoRsError>;
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandFailure, RepoRsError};
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    #[test]
+    fn displays_command_failures() {
+        let output = Command::new("sh")
+            .args(["-c", "seq 1 15 >&2; exit 3"])
+            .output()
+            .unwrap();
+        let failure =
+            CommandFailure::new("sh -c 'exit 3'".to_string(), PathBuf::from("/tmp"), &output);
+
+        assert_eq!(Some(3), failure.code);
+        assert_eq!(None, failure.signal);
+        assert_eq!(10, failure.stderr.lines().count());
+        assert!(failure.stderr.starts_with("6\n"));
 
-    fn unwrap_or_exit(self, message: &str) -> T {
-        self.unwrap_or_else(|e| {
-            let err = clap::Error::with_description(
-                &format!("{}: {}", message, e),
-                clap::ErrorKind::InvalidValue,
-            );
-            err.exit()
-        })
+        let err = RepoRsError::CommandFailed("a".to_string(), Box::new(failure), Box::new(output));
+        let text = err.to_string();
+        assert!(
+            text.starts_with("`sh -c 'exit 3'` exited with code 3 in 'a' (/tmp)\n    | 6\n"),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("    | 15"), "{}", text);
     }
 }
@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::process;
+
+use colored::*;
+
+use repo_rs::summary::Summary;
+use repo_rs::RepoRsError;
+
+/// The exit status of the process for each class of failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Nothing succeeded, either because a command that doesn't operate on
+    /// repos failed or because every repo failed
+    Failure = 1,
+
+    /// The command line or the environment was invalid
+    Usage = 2,
+
+    /// The config could not be loaded, locked or saved, or has problems
+    Config = 3,
+
+    /// Some repos failed while others succeeded
+    PartialFailure = 4,
+
+    /// The user pressed Ctrl-C
+    Interrupted = 130,
+}
+
+pub type CliResult<T = ()> = std::result::Result<T, Failure>;
+
+/// A failure that ends the process, along with the errors that caused it and
+/// the keys of the repos they happened in, if any
+#[derive(Debug)]
+pub struct Failure {
+    code: ExitCode,
+    message: String,
+    errors: Vec<(Option<String>, RepoRsError)>,
+}
+
+impl Failure {
+    pub fn new(code: ExitCode, message: &str) -> Failure {
+        Failure {
+            code,
+            message: message.to_string(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn usage(message: &str) -> Failure {
+        Failure::new(ExitCode::Usage, message)
+    }
+
+    pub fn code(&self) -> ExitCode {
+        self.code
+    }
+
+    pub fn with_error(mut self, key: Option<&str>, error: RepoRsError) -> Failure {
+        self.errors.push((key.map(String::from), error));
+        self
+    }
+
+    /// Describes how many repos of an operation failed, or returns `None` if
+    /// none failed and it wasn't interrupted. The errors themselves were
+    /// already shown by the reporter, so they aren't repeated.
+    pub fn from_summary(message: &str, summary: Summary) -> Option<Failure> {
+        if summary.interrupted {
+            return Some(Failure::new(ExitCode::Interrupted, "Interrupted"));
+        }
+
+        let total = summary.succeeded() + summary.failed() + summary.skipped();
+        let code = match (summary.failed(), summary.succeeded()) {
+            (0, _) => return None,
+            (_, 0) => ExitCode::Failure,
+            _ => ExitCode::PartialFailure,
+        };
+
+        let message = format!("{} ({} of {} failed)", message, summary.failed(), total);
+        Some(Failure::new(code, &message))
+    }
+
+    /// Prints the report of what went wrong and exits with the failure's code
+    pub fn exit(self) -> ! {
+        eprintln!("{} {}", "error:".red().bold(), self.message);
+
+        for (key, error) in &self.errors {
+            match key {
+                Some(key) => eprintln!("  {}: {}", key.white().bold(), error),
+                None => eprintln!("  {}", error),
+            }
+
+            // errors that wrap another often display as the wrapped error, so
+            // skip causes that would just repeat the line before
+            let mut last = error.to_string();
+            let mut source = error.source();
+            while let Some(cause) = source {
+                let text = cause.to_string();
+                if text != last {
+                    eprintln!("    caused by: {}", text);
+                }
+                last = text;
+                source = cause.source();
+            }
+        }
+
+        process::exit(self.code as i32)
+    }
+}
+
+/// Converts errors into failures with the given exit code and message
+pub trait Context<T> {
+    fn context(self, code: ExitCode, message: &str) -> CliResult<T>;
+}
+
+impl<T, E: Into<RepoRsError>> Context<T> for Result<T, E> {
+    fn context(self, code: ExitCode, message: &str) -> CliResult<T> {
+        self.map_err(|e| Failure::new(code, message).with_error(None, e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExitCode, Failure};
+    use repo_rs::summary::{Outcome, Summary};
+    use repo_rs::RepoRsError;
+
+    fn summary(outcomes: Vec<Outcome>) -> Summary {
+        let mut summary = Summary::default();
+        for (i, outcome) in outcomes.into_iter().enumerate() {
+            summary.push(i.to_string(), outcome);
+        }
+        summary
+    }
+
+    fn failed() -> Outcome {
+        Outcome::Failed(RepoRsError::NoRepo("foo".to_string()))
+    }
+
+    #[test]
+    fn classifies_summaries() {
+        let code = |s| Failure::from_summary("failed", s).map(|f| f.code());
+
+        assert_eq!(None, code(summary(vec![Outcome::Succeeded])));
+        assert_eq!(None, code(summary(vec![Outcome::Skipped("x".to_string())])));
+        assert_eq!(Some(ExitCode::Failure), code(summary(vec![failed()])));
+        assert_eq!(
+            Some(ExitCode::PartialFailure),
+            code(summary(vec![failed(), Outcome::Succeeded]))
+        );
+
+        let mut interrupted = summary(vec![Outcome::Succeeded]);
+        interrupted.interrupted = true;
+        assert_eq!(Some(ExitCode::Interrupted), code(interrupted));
+    }
+
+    #[test]
+    fn counts_failures_without_repeating_them() {
+        let failure = Failure::from_summary(
+            "Not all commands succeeded",
+            summary(vec![failed(), Outcome::Succeeded, failed()]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "Not all commands succeeded (2 of 3 failed)",
+            failure.message
+        );
+        assert!(failure.errors.is_empty());
+    }
+
+    #[test]
+    fn exit_codes() {
+        let codes = [
+            ExitCode::Failure,
+            ExitCode::Usage,
+            ExitCode::Config,
+            ExitCode::PartialFailure,
+            ExitCode::Interrupted,
+        ];
+        let values: Vec<i32> = codes.iter().map(|&c| c as i32).collect();
+        assert_eq!(vec![1, 2, 3, 4, 130], values);
+        assert_eq!(ExitCode::Usage, Failure::usage("bad").code());
+    }
+}
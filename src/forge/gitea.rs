@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use serde_derive::Deserialize;
+
+use super::{encode, find_token, Api, Forge, Owner, RemoteRepo, Token};
+use crate::error::{RepoRsError, Result};
+
+/// The default maximum page size of Gitea and Forgejo
+const MAX_PER_PAGE: u32 = 50;
+
+const HINT: &str = "Set GITEA_TOKEN or add a token to the forge's settings in the config";
+
+/// A repository as described by the Gitea API
+#[derive(Debug, Deserialize)]
+struct Repository {
+    name: String,
+    full_name: String,
+    default_branch: Option<String>,
+    ssh_url: String,
+    clone_url: String,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    empty: bool,
+}
+
+/// A client for the API of a Gitea or Forgejo instance
+pub struct Gitea {
+    api: Api,
+}
+
+impl Gitea {
+    /// Creates a client for the API at `api_url`, such as
+    /// `https://gitea.example.com/api/v1`, authenticating with `GITEA_TOKEN`,
+    /// `FORGEJO_TOKEN` or else `token` from the config. There is no public
+    /// instance to default to, so `api_url` is required.
+    pub fn new(api_url: Option<&str>, token: Option<&str>) -> Result<Gitea> {
+        let api_url = api_url.ok_or_else(|| RepoRsError::NoApiUrl("Gitea".to_string()))?;
+        let token = find_token(&["GITEA_TOKEN", "FORGEJO_TOKEN"], token);
+        Gitea::with_token(api_url, token)
+    }
+
+    /// Creates a client for the API at `api_url` that authenticates with
+    /// `token`, without looking for one elsewhere
+    fn with_token(api_url: &str, token: Option<Token>) -> Result<Gitea> {
+        let auth = (AUTHORIZATION, token_header as fn(&str) -> String);
+        let api = Api::new(api_url, HeaderMap::new(), token, auth, HINT)?
+            .with_page_size("limit", MAX_PER_PAGE);
+
+        Ok(Gitea { api })
+    }
+
+    /// Lists `per_page` items per request, up to 50, instead of the maximum
+    pub fn with_per_page(mut self, per_page: u32) -> Gitea {
+        self.api = self
+            .api
+            .with_page_size("limit", per_page.clamp(1, MAX_PER_PAGE));
+        self
+    }
+
+    /// Caches responses in `dir` and revalidates them by their ETag
+    pub fn with_cache(mut self, dir: PathBuf) -> Gitea {
+        self.api = self.api.with_cache(dir);
+        self
+    }
+}
+
+#[async_trait]
+impl Forge for Gitea {
+    fn name(&self) -> &'static str {
+        "Gitea"
+    }
+
+    async fn repos(&self, owner: &Owner) -> Result<Vec<RemoteRepo>> {
+        let path = match *owner {
+            Owner::Org(ref org) => format!("orgs/{}/repos", encode(org)),
+            Owner::User(ref user) => format!("users/{}/repos", encode(user)),
+        };
+
+        let repos = self.api.get_pages::<Repository>(&path).await?;
+        Ok(repos
+            .into_iter()
+            .map(|r| RemoteRepo {
+                name: r.name,
+                full_name: r.full_name,
+                // empty repositories still report the branch they would use
+                default_branch: match r.empty {
+                    true => None,
+                    false => r.default_branch,
+                },
+                ssh_url: r.ssh_url,
+                https_url: r.clone_url,
+                archived: r.archived,
+            })
+            .collect())
+    }
+}
+
+fn token_header(token: &str) -> String {
+    format!("token {}", token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Gitea;
+    use crate::error::RepoRsError;
+    use crate::forge::mock::Mock;
+    use crate::forge::{Forge, Owner, Token, TokenSource};
+
+    fn config_token(token: &str) -> Option<Token> {
+        Some((token.to_string(), TokenSource::Config))
+    }
+
+    #[tokio::test]
+    async fn lists_user_repos() {
+        let mock = Mock::start().await;
+        mock.route(
+            "/users/alice/repos?limit=50",
+            200,
+            &[],
+            r#"[{"name": "dots", "full_name": "alice/dots", "default_branch": "main",
+                "ssh_url": "git@gitea.example.com:alice/dots.git",
+                "clone_url": "https://gitea.example.com/alice/dots.git",
+                "archived": true, "empty": false}]"#,
+        );
+
+        let gitea = Gitea::with_token(&mock.url, config_token("secret")).unwrap();
+        let repos = gitea
+            .repos(&Owner::User("alice".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(Some("main".to_string()), repos[0].default_branch);
+        assert!(repos[0].archived);
+        assert_eq!(
+            Some("token secret"),
+            mock.requests()[0].header("authorization")
+        );
+    }
+
+    #[tokio::test]
+    async fn explains_rejected_tokens() {
+        let mock = Mock::start().await;
+        mock.route(
+            "/orgs/team/repos?limit=50",
+            401,
+            &[],
+            r#"{"message": "token is invalid"}"#,
+        );
+
+        let gitea = Gitea::with_token(&mock.url, config_token("stale")).unwrap();
+        match gitea.repos(&Owner::Org("team".to_string())).await {
+            Err(RepoRsError::Unauthorized(host, source, _)) => {
+                assert_eq!("127.0.0.1", host);
+                assert_eq!(Some("the config".to_string()), source);
+            }
+            other => panic!("expected an authorization error, got {:?}", other),
+        }
+
+        assert!(Gitea::new(None, None).is_err());
+    }
+}
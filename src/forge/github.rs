@@ -0,0 +1,673 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, ACCEPT, AUTHORIZATION};
+use reqwest::Method;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{encode, find_token, host, Api, Forge, Owner, RemoteRepo, Token, TokenSource};
+use crate::config::xdg_config_dir;
+use crate::error::{RepoRsError, Result};
+
+/// The API used unless another is configured
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// The largest page of a list the API returns
+const MAX_PER_PAGE: u32 = 100;
+
+const HINT: &str = "Set GH_TOKEN or GITHUB_TOKEN (GH_ENTERPRISE_TOKEN for GitHub Enterprise), \
+                    add a token to the config, or run `gh auth login`";
+
+/// A repository as described by the GitHub API
+#[derive(Debug, Deserialize)]
+struct Repository {
+    name: String,
+    full_name: String,
+    default_branch: Option<String>,
+    ssh_url: String,
+    clone_url: String,
+    #[serde(default)]
+    archived: bool,
+}
+
+impl From<Repository> for RemoteRepo {
+    fn from(r: Repository) -> RemoteRepo {
+        RemoteRepo {
+            name: r.name,
+            full_name: r.full_name,
+            default_branch: r.default_branch,
+            ssh_url: r.ssh_url,
+            https_url: r.clone_url,
+            archived: r.archived,
+        }
+    }
+}
+
+/// A pull request to open
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NewPullRequest {
+    pub title: String,
+    pub body: String,
+    /// The branch with the changes
+    pub head: String,
+    /// The branch the changes are to be merged into
+    pub base: String,
+    pub draft: bool,
+}
+
+/// A pull request that was opened, or that was already open for the branch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OpenedPullRequest {
+    pub number: u64,
+    pub url: String,
+    /// Whether it was opened just now
+    pub created: bool,
+}
+
+/// An open pull request, along with the state of its reviews and checks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+    pub author: String,
+    /// When the pull request was opened, as an RFC 3339 timestamp
+    pub created_at: String,
+    pub draft: bool,
+    pub url: String,
+    /// The users whose review has been requested and not yet given
+    pub requested_reviewers: Vec<String>,
+    pub review: ReviewState,
+    pub checks: CheckState,
+}
+
+impl PullRequest {
+    /// How long the pull request has been open, if its timestamp is valid
+    pub fn age(&self, now: SystemTime) -> Option<Duration> {
+        now.duration_since(parse_timestamp(&self.created_at)?).ok()
+    }
+}
+
+/// The overall outcome of the reviews of a pull request, considering only the
+/// latest review from each reviewer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewState {
+    Approved,
+    ChangesRequested,
+    /// Reviews have been requested, but none approved or requested changes
+    ReviewRequired,
+    None,
+}
+
+/// The combined outcome of the commit statuses and check runs of the head
+/// commit of a pull request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckState {
+    Success,
+    Failure,
+    Pending,
+    None,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Commit {
+    sha: String,
+}
+
+/// A pull request as described by the GitHub API
+#[derive(Debug, Deserialize)]
+struct Pull {
+    number: u64,
+    title: String,
+    user: User,
+    created_at: String,
+    #[serde(default)]
+    draft: bool,
+    html_url: String,
+    head: Commit,
+    #[serde(default)]
+    requested_reviewers: Vec<User>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Review {
+    user: Option<User>,
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CombinedStatus {
+    state: String,
+    total_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRuns {
+    check_runs: Vec<CheckRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRun {
+    status: String,
+    conclusion: Option<String>,
+}
+
+/// A client for the GitHub API, or a GitHub Enterprise server's
+pub struct Github {
+    api: Api,
+    host: String,
+}
+
+impl Github {
+    /// Creates a client for the API at `api_url`, authenticating with the
+    /// first token found in the environment, `token` from the config or the
+    /// gh CLI's hosts file
+    pub fn new(api_url: Option<&str>, token: Option<&str>) -> Result<Github> {
+        let api_url = api_url.unwrap_or(DEFAULT_API_URL);
+        let token = github_token(&web_host(&host(api_url)?), token);
+        Github::with_token(api_url, token)
+    }
+
+    /// Creates a client for the API at `api_url` that authenticates with
+    /// `token`, without looking for one elsewhere
+    fn with_token(api_url: &str, token: Option<Token>) -> Result<Github> {
+        let host = web_host(&host(api_url)?);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, "application/vnd.github+json".parse().unwrap());
+        headers.insert("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
+        let auth = (AUTHORIZATION, bearer as fn(&str) -> String);
+        let api =
+            Api::new(api_url, headers, token, auth, HINT)?.with_page_size("per_page", MAX_PER_PAGE);
+
+        Ok(Github { api, host })
+    }
+
+    /// Lists `per_page` items per request, up to 100, instead of the maximum
+    pub fn with_per_page(mut self, per_page: u32) -> Github {
+        self.api = self
+            .api
+            .with_page_size("per_page", per_page.clamp(1, MAX_PER_PAGE));
+        self
+    }
+
+    /// Caches responses in `dir` and revalidates them by their ETag, since
+    /// requests answered with `304 Not Modified` don't count against the rate
+    /// limit
+    pub fn with_cache(mut self, dir: PathBuf) -> Github {
+        self.api = self.api.with_cache(dir);
+        self
+    }
+
+    /// The host of the web interface, which is also the host of the remotes of
+    /// repositories on this server
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Where the token in use was found, if there is one
+    pub fn token_source(&self) -> Option<&TokenSource> {
+        self.api.token.as_ref().map(|(_, source)| source)
+    }
+
+    /// The login of the user the token belongs to
+    pub async fn current_user(&self) -> Result<String> {
+        Ok(self.api.get_json::<User>("user").await?.login)
+    }
+
+    /// Describes the repository named `owner/name`
+    pub async fn repo(&self, full_name: &str) -> Result<RemoteRepo> {
+        let repo = self
+            .api
+            .get_json::<Repository>(&format!("repos/{}", full_name))
+            .await?;
+        Ok(repo.into())
+    }
+
+    /// Opens a pull request in the repository named `owner/name`. If one is
+    /// already open for the same branches, that one is returned instead.
+    pub async fn create_pull_request(
+        &self,
+        full_name: &str,
+        pr: &NewPullRequest,
+    ) -> Result<OpenedPullRequest> {
+        let path = format!("repos/{}/pulls", full_name);
+        let (url, message) = match self.api.send_json::<Pull>(Method::POST, &path, pr).await {
+            Ok(pull) => {
+                return Ok(OpenedPullRequest {
+                    number: pull.number,
+                    url: pull.html_url,
+                    created: true,
+                })
+            }
+            // the API refuses to open a second pull request for a branch
+            Err(RepoRsError::ApiError(url, 422, message)) => (url, message),
+            Err(e) => return Err(e),
+        };
+
+        let owner = full_name.split('/').next().unwrap_or(full_name);
+        let open = self
+            .api
+            .get_pages::<Pull>(&format!(
+                "{}?state=open&head={}&base={}",
+                path,
+                encode(&format!("{}:{}", owner, pr.head)),
+                encode(&pr.base)
+            ))
+            .await?;
+
+        match open.into_iter().next() {
+            Some(pull) => Ok(OpenedPullRequest {
+                number: pull.number,
+                url: pull.html_url,
+                created: false,
+            }),
+            None => Err(RepoRsError::ApiError(url, 422, message)),
+        }
+    }
+
+    /// Replaces the description of a pull request
+    pub async fn set_pull_request_body(
+        &self,
+        full_name: &str,
+        number: u64,
+        body: &str,
+    ) -> Result<()> {
+        let path = format!("repos/{}/pulls/{}", full_name, number);
+        self.api
+            .send_json::<Value>(Method::PATCH, &path, &json!({ "body": body }))
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the open pull requests of the repository named `owner/name`,
+    /// oldest first
+    pub async fn pull_requests(&self, full_name: &str) -> Result<Vec<PullRequest>> {
+        let pulls = self
+            .api
+            .get_pages::<Pull>(&format!(
+                "repos/{}/pulls?state=open&sort=created&direction=asc",
+                full_name
+            ))
+            .await?;
+
+        let mut prs = Vec::new();
+        for pull in pulls {
+            let reviews = self
+                .api
+                .get_pages::<Review>(&format!(
+                    "repos/{}/pulls/{}/reviews",
+                    full_name, pull.number
+                ))
+                .await?;
+            let status = self
+                .api
+                .get_json::<CombinedStatus>(&format!(
+                    "repos/{}/commits/{}/status",
+                    full_name, pull.head.sha
+                ))
+                .await?;
+            let runs = self
+                .api
+                .get_json::<CheckRuns>(&format!(
+                    "repos/{}/commits/{}/check-runs",
+                    full_name, pull.head.sha
+                ))
+                .await?;
+
+            let requested_reviewers = pull
+                .requested_reviewers
+                .into_iter()
+                .map(|u| u.login)
+                .collect::<Vec<_>>();
+
+            prs.push(PullRequest {
+                number: pull.number,
+                title: pull.title,
+                author: pull.user.login,
+                created_at: pull.created_at,
+                draft: pull.draft,
+                url: pull.html_url,
+                review: review_state(&reviews, !requested_reviewers.is_empty()),
+                checks: check_state(&status, &runs.check_runs),
+                requested_reviewers,
+            });
+        }
+
+        Ok(prs)
+    }
+}
+
+/// Summarizes reviews, which the API lists oldest first
+fn review_state(reviews: &[Review], requested: bool) -> ReviewState {
+    let mut latest: Vec<(&str, &str)> = Vec::new();
+    for review in reviews {
+        // comments neither approve nor block, and pending reviews are unsent
+        if review.state != "APPROVED" && review.state != "CHANGES_REQUESTED" {
+            continue;
+        }
+        let user = review.user.as_ref().map_or("", |u| u.login.as_str());
+        latest.retain(|(u, _)| *u != user);
+        latest.push((user, &review.state));
+    }
+
+    if latest.iter().any(|(_, s)| *s == "CHANGES_REQUESTED") {
+        ReviewState::ChangesRequested
+    } else if !latest.is_empty() {
+        ReviewState::Approved
+    } else if requested {
+        ReviewState::ReviewRequired
+    } else {
+        ReviewState::None
+    }
+}
+
+/// Combines the legacy commit statuses with check runs, either of which may
+/// be used by a repository's CI
+fn check_state(status: &CombinedStatus, runs: &[CheckRun]) -> CheckState {
+    let mut states = runs
+        .iter()
+        .map(
+            |run| match (run.status.as_str(), run.conclusion.as_deref()) {
+                ("completed", Some("success" | "neutral" | "skipped")) => CheckState::Success,
+                ("completed", _) => CheckState::Failure,
+                _ => CheckState::Pending,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    // the combined state is pending when there are no statuses at all
+    if status.total_count > 0 {
+        states.push(match status.state.as_str() {
+            "success" => CheckState::Success,
+            "pending" => CheckState::Pending,
+            _ => CheckState::Failure,
+        });
+    }
+
+    if states.contains(&CheckState::Failure) {
+        CheckState::Failure
+    } else if states.contains(&CheckState::Pending) {
+        CheckState::Pending
+    } else if states.is_empty() {
+        CheckState::None
+    } else {
+        CheckState::Success
+    }
+}
+
+/// Parses a UTC timestamp like `2024-01-31T12:00:00Z`, as used by the API
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // days since the epoch of the proleptic Gregorian calendar date
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[async_trait]
+impl Forge for Github {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    async fn repos(&self, owner: &Owner) -> Result<Vec<RemoteRepo>> {
+        let path = match *owner {
+            Owner::Org(ref org) => format!("orgs/{}/repos", encode(org)),
+            Owner::User(ref user) => format!("users/{}/repos", encode(user)),
+        };
+
+        let repos = self.api.get_pages::<Repository>(&path).await?;
+        Ok(repos.into_iter().map(RemoteRepo::from).collect())
+    }
+}
+
+fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+/// The host of the web interface an API host belongs to, which is how tokens
+/// are looked up by the gh CLI
+fn web_host(api_host: &str) -> String {
+    api_host
+        .strip_prefix("api.")
+        .unwrap_or(api_host)
+        .to_string()
+}
+
+/// Finds a token for `host`. As with the gh CLI, github.com uses `GH_TOKEN` or
+/// `GITHUB_TOKEN` while Enterprise servers use `GH_ENTERPRISE_TOKEN` or
+/// `GITHUB_ENTERPRISE_TOKEN`.
+fn github_token(host: &str, config: Option<&str>) -> Option<Token> {
+    let vars: &[&'static str] = match host {
+        "github.com" => &["GH_TOKEN", "GITHUB_TOKEN"],
+        _ => &["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"],
+    };
+
+    if let Some(token) = find_token(vars, config) {
+        return Some(token);
+    }
+
+    let hosts_file = match env::var_os("GH_CONFIG_DIR") {
+        Some(dir) => PathBuf::from(dir).join("hosts.yml"),
+        None => xdg_config_dir().join("gh").join("hosts.yml"),
+    };
+    let content = fs::read_to_string(&hosts_file).ok()?;
+    let token = gh_token(&content, host)?;
+
+    Some((token, TokenSource::GhCli(hosts_file)))
+}
+
+/// Reads the token for `host` from the contents of the gh CLI's hosts file.
+/// Newer versions of gh keep tokens in the system keyring instead, in which
+/// case there is none to find.
+fn gh_token(hosts: &str, host: &str) -> Option<String> {
+    let hosts: Value = serde_yaml::from_str(hosts).ok()?;
+    hosts[host]["oauth_token"].as_str().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        gh_token, parse_timestamp, web_host, CheckState, Github, NewPullRequest, ReviewState,
+    };
+    use crate::error::RepoRsError;
+    use crate::forge::mock::Mock;
+    use crate::forge::{Forge, Owner, Token, TokenSource};
+    use serde_json::Value;
+    use std::time::{Duration, SystemTime};
+
+    fn secret() -> Option<Token> {
+        Some(("secret".to_string(), TokenSource::Config))
+    }
+
+    #[test]
+    fn timestamps() {
+        let epoch = |secs| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(epoch(0), parse_timestamp("1970-01-01T00:00:00Z"));
+        assert_eq!(epoch(951_782_400), parse_timestamp("2000-02-29T00:00:00Z"));
+        assert_eq!(
+            epoch(1_706_702_400),
+            parse_timestamp("2024-01-31T12:00:00Z")
+        );
+        assert_eq!(None, parse_timestamp("2024-01-31"));
+    }
+
+    #[tokio::test]
+    async fn summarizes_pull_requests() {
+        let mock = Mock::start().await;
+        mock.route(
+            "/repos/octo/app/pulls?state=open&sort=created&direction=asc&per_page=100",
+            200,
+            &[],
+            r#"[{"number": 7, "title": "Add a thing", "user": {"login": "alice"},
+                "created_at": "2024-01-31T12:00:00Z", "draft": true,
+                "html_url": "https://github.com/octo/app/pull/7", "head": {"sha": "abc"},
+                "requested_reviewers": [{"login": "carol"}]}]"#,
+        );
+        mock.route(
+            "/repos/octo/app/pulls/7/reviews?per_page=100",
+            200,
+            &[],
+            r#"[{"user": {"login": "bob"}, "state": "CHANGES_REQUESTED"},
+                {"user": {"login": "bob"}, "state": "COMMENTED"},
+                {"user": {"login": "bob"}, "state": "APPROVED"}]"#,
+        );
+        mock.route(
+            "/repos/octo/app/commits/abc/status",
+            200,
+            &[],
+            r#"{"state": "pending", "total_count": 0}"#,
+        );
+        mock.route(
+            "/repos/octo/app/commits/abc/check-runs",
+            200,
+            &[],
+            r#"{"total_count": 2, "check_runs": [
+                {"status": "completed", "conclusion": "success"},
+                {"status": "in_progress", "conclusion": null}]}"#,
+        );
+
+        let gh = Github::with_token(&mock.url, secret()).unwrap();
+        let prs = gh.pull_requests("octo/app").await.unwrap();
+
+        assert_eq!(1, prs.len());
+        assert_eq!("alice", prs[0].author);
+        assert!(prs[0].draft);
+        assert_eq!(vec!["carol"], prs[0].requested_reviewers);
+        assert_eq!(ReviewState::Approved, prs[0].review);
+        assert_eq!(CheckState::Pending, prs[0].checks);
+    }
+
+    #[tokio::test]
+    async fn opens_pull_requests_once() {
+        let mock = Mock::start().await;
+        let pull = r#"{"number": 9, "title": "Bump", "user": {"login": "alice"},
+            "created_at": "2024-01-31T12:00:00Z", "html_url": "https://github.com/octo/app/pull/9",
+            "head": {"sha": "abc"}}"#;
+        mock.route("/repos/octo/app/pulls", 201, &[], pull);
+        mock.route(
+            "/repos/octo/lib/pulls",
+            422,
+            &[],
+            r#"{"message": "Validation Failed",
+                "errors": [{"message": "A pull request already exists for octo:bump."}]}"#,
+        );
+        mock.route(
+            "/repos/octo/lib/pulls?state=open&head=octo%3Abump&base=main&per_page=100",
+            200,
+            &[],
+            &format!("[{}]", pull),
+        );
+
+        let gh = Github::with_token(&mock.url, secret()).unwrap();
+        let pr = NewPullRequest {
+            title: "Bump".to_string(),
+            body: String::new(),
+            head: "bump".to_string(),
+            base: "main".to_string(),
+            draft: true,
+        };
+
+        let created = gh.create_pull_request("octo/app", &pr).await.unwrap();
+        assert_eq!(9, created.number);
+        assert!(created.created);
+        let request = &mock.requests()[0];
+        assert_eq!("POST", request.method);
+        let sent: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!("bump", sent["head"]);
+        assert_eq!(true, sent["draft"]);
+
+        let existing = gh.create_pull_request("octo/lib", &pr).await.unwrap();
+        assert_eq!("https://github.com/octo/app/pull/9", existing.url);
+        assert!(!existing.created);
+
+        match gh.create_pull_request("octo/other", &pr).await {
+            Err(RepoRsError::ApiError(_, 404, _)) => {}
+            other => panic!("expected the repository to be missing, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn web_hosts() {
+        assert_eq!("github.com", web_host("api.github.com"));
+        assert_eq!("github.example.com", web_host("github.example.com"));
+    }
+
+    #[test]
+    fn reads_gh_hosts_file() {
+        let hosts = "github.com:\n    user: octocat\n    oauth_token: gho_abc\n    \
+                     git_protocol: https\nghe.example.com:\n    user: octocat\n";
+        assert_eq!(Some("gho_abc".to_string()), gh_token(hosts, "github.com"));
+        assert_eq!(None, gh_token(hosts, "ghe.example.com"));
+        assert_eq!(None, gh_token(hosts, "gitlab.com"));
+    }
+
+    #[tokio::test]
+    async fn lists_org_repos_across_pages() {
+        let mock = Mock::start().await;
+        let repo = |name: &str, archived: bool| {
+            format!(
+                r#"{{"name": "{0}", "full_name": "octo/{0}", "default_branch": "main",
+                "ssh_url": "git@github.com:octo/{0}.git",
+                "clone_url": "https://github.com/octo/{0}.git", "archived": {1}}}"#,
+                name, archived
+            )
+        };
+        let next = format!(
+            "<{}/orgs/octo/repos?per_page=1&page=2>; rel=\"next\"",
+            mock.url
+        );
+        mock.route(
+            "/orgs/octo/repos?per_page=1",
+            200,
+            &[("Link", &next)],
+            &format!("[{}]", repo("a", false)),
+        );
+        mock.route(
+            "/orgs/octo/repos?per_page=1&page=2",
+            200,
+            &[],
+            &format!("[{}]", repo("b", true)),
+        );
+
+        let gh = Github::with_token(&mock.url, secret())
+            .unwrap()
+            .with_per_page(1);
+        let repos = gh.repos(&Owner::Org("octo".to_string())).await.unwrap();
+
+        assert_eq!(
+            vec!["octo/a", "octo/b"],
+            repos.iter().map(|r| &r.full_name).collect::<Vec<_>>()
+        );
+        assert_eq!("https://github.com/octo/a.git", repos[0].https_url);
+        assert!(!repos[0].archived && repos[1].archived);
+
+        let requests = mock.requests();
+        assert_eq!(2, requests.len());
+        assert_eq!("/orgs/octo/repos?per_page=1&page=2", requests[1].path);
+        assert_eq!(Some("Bearer secret"), requests[0].header("authorization"));
+    }
+}
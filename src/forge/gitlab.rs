@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName};
+use serde_derive::Deserialize;
+
+use super::{encode, find_token, Api, Forge, Owner, RemoteRepo, Token};
+use crate::error::Result;
+
+/// The API used unless another is configured
+pub const DEFAULT_API_URL: &str = "https://gitlab.com/api/v4";
+
+/// The most items GitLab returns per page of a list
+const MAX_PER_PAGE: u32 = 100;
+
+const HINT: &str = "Set GITLAB_TOKEN or add a token to the forge's settings in the config";
+
+/// A project as described by the GitLab API
+#[derive(Debug, Deserialize)]
+struct Project {
+    path: String,
+    path_with_namespace: String,
+    default_branch: Option<String>,
+    ssh_url_to_repo: String,
+    http_url_to_repo: String,
+    #[serde(default)]
+    archived: bool,
+}
+
+/// A client for the API of GitLab or a self-hosted instance
+pub struct Gitlab {
+    api: Api,
+}
+
+impl Gitlab {
+    /// Creates a client for the API at `api_url`, authenticating with
+    /// `GITLAB_TOKEN` or else `token` from the config
+    pub fn new(api_url: Option<&str>, token: Option<&str>) -> Result<Gitlab> {
+        let token = find_token(&["GITLAB_TOKEN"], token);
+        Gitlab::with_token(api_url.unwrap_or(DEFAULT_API_URL), token)
+    }
+
+    /// Creates a client for the API at `api_url` that authenticates with
+    /// `token`, without looking for one elsewhere
+    fn with_token(api_url: &str, token: Option<Token>) -> Result<Gitlab> {
+        let auth = (
+            HeaderName::from_static("private-token"),
+            private_token as fn(&str) -> String,
+        );
+        let api = Api::new(api_url, HeaderMap::new(), token, auth, HINT)?
+            .with_page_size("per_page", MAX_PER_PAGE);
+
+        Ok(Gitlab { api })
+    }
+
+    /// Lists `per_page` items per request, up to 100, instead of the maximum
+    pub fn with_per_page(mut self, per_page: u32) -> Gitlab {
+        self.api = self
+            .api
+            .with_page_size("per_page", per_page.clamp(1, MAX_PER_PAGE));
+        self
+    }
+
+    /// Caches responses in `dir` and revalidates them by their ETag
+    pub fn with_cache(mut self, dir: PathBuf) -> Gitlab {
+        self.api = self.api.with_cache(dir);
+        self
+    }
+}
+
+#[async_trait]
+impl Forge for Gitlab {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    async fn repos(&self, owner: &Owner) -> Result<Vec<RemoteRepo>> {
+        let path = match *owner {
+            Owner::Org(ref group) => {
+                format!("groups/{}/projects?include_subgroups=true", encode(group))
+            }
+            Owner::User(ref user) => format!("users/{}/projects", encode(user)),
+        };
+
+        let projects = self.api.get_pages::<Project>(&path).await?;
+        Ok(projects
+            .into_iter()
+            .map(|p| RemoteRepo {
+                name: p.path,
+                full_name: p.path_with_namespace,
+                default_branch: p.default_branch,
+                ssh_url: p.ssh_url_to_repo,
+                https_url: p.http_url_to_repo,
+                archived: p.archived,
+            })
+            .collect())
+    }
+}
+
+fn private_token(token: &str) -> String {
+    token.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Gitlab;
+    use crate::forge::mock::Mock;
+    use crate::forge::{Forge, Owner, TokenSource};
+
+    #[tokio::test]
+    async fn lists_projects_in_nested_groups() {
+        let mock = Mock::start().await;
+        mock.route(
+            "/groups/team%2Ftools/projects?include_subgroups=true&per_page=100",
+            200,
+            &[],
+            r#"[{"path": "cli", "path_with_namespace": "team/tools/cli",
+                "default_branch": null, "ssh_url_to_repo": "git@gitlab.example.com:team/tools/cli.git",
+                "http_url_to_repo": "https://gitlab.example.com/team/tools/cli.git",
+                "archived": false}]"#,
+        );
+
+        let gitlab =
+            Gitlab::with_token(&mock.url, Some(("secret".to_string(), TokenSource::Config)))
+                .unwrap();
+        let repos = gitlab
+            .repos(&Owner::Org("team/tools".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(1, repos.len());
+        assert_eq!("cli", repos[0].name);
+        assert_eq!("team/tools/cli", repos[0].full_name);
+        assert_eq!(None, repos[0].default_branch);
+        assert_eq!(Some("secret"), mock.requests()[0].header("private-token"));
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request received by the mock server
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// The path including the query string
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Clone)]
+struct Route {
+    path: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<Request>,
+}
+
+/// An HTTP server on a local port that answers requests with canned responses
+/// and records them, for testing API clients. Paths without a route get a 404.
+/// A path with several routes gets their responses in turn, the last one
+/// repeating.
+pub struct Mock {
+    /// The base URL of the server, without a trailing slash
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl Mock {
+    pub async fn start() -> Mock {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(respond(stream, server_state.clone()));
+            }
+        });
+
+        Mock { url, state }
+    }
+
+    /// Answers requests for `path`, which includes any query string
+    pub fn route(&self, path: &str, status: u16, headers: &[(&str, &str)], body: &str) {
+        self.state.lock().unwrap().routes.push(Route {
+            path: path.to_string(),
+            status,
+            headers: headers
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+            body: body.to_string(),
+        });
+    }
+
+    /// The requests received so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+/// Reads a single request and writes the response for it
+async fn respond(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    let head_len = loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("GET").to_string();
+    let path = request_line.next().unwrap_or("/").to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(": "))
+        .map(|(n, v)| (n.to_string(), v.to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < head_len + content_length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let body = String::from_utf8_lossy(&buf[head_len..]).into_owned();
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            method,
+            path: path.clone(),
+            headers,
+            body,
+        });
+
+        let matching = state.routes.iter().filter(|r| r.path == path).count();
+        let index = state.routes.iter().position(|r| r.path == path);
+        let route = match index {
+            Some(i) if matching > 1 => Some(state.routes.remove(i)),
+            Some(i) => Some(state.routes[i].clone()),
+            None => None,
+        };
+
+        let mut response = match route {
+            Some(route) => {
+                let mut head = format!("HTTP/1.1 {} Mock\r\n", route.status);
+                for (name, value) in &route.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                (head, route.body)
+            }
+            None => (
+                "HTTP/1.1 404 Not Found\r\n".to_string(),
+                r#"{"message": "Not Found"}"#.to_string(),
+            ),
+        };
+        response.0.push_str(&format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.1.len()
+        ));
+        response.0 + &response.1
+    };
+
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
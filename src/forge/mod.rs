@@ -0,0 +1,778 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::header::{HeaderMap, HeaderName, ETAG, IF_NONE_MATCH, LINK};
+use reqwest::{Method, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time;
+
+use crate::error::{RepoRsError, Result};
+use crate::settings::ForgeKind;
+
+mod gitea;
+mod github;
+mod gitlab;
+#[cfg(test)]
+mod mock;
+
+pub use gitea::Gitea;
+pub use github::{CheckState, Github, NewPullRequest, OpenedPullRequest, PullRequest, ReviewState};
+pub use gitlab::Gitlab;
+
+/// A repository hosted on a forge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemoteRepo {
+    pub name: String,
+    /// The name including the user, organization or group that owns it
+    pub full_name: String,
+    /// The branch checked out by clones, or `None` for empty repositories
+    pub default_branch: Option<String>,
+    pub ssh_url: String,
+    pub https_url: String,
+    pub archived: bool,
+}
+
+/// Whose repositories to list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Owner {
+    /// An organization, or a GitLab group including its subgroups
+    Org(String),
+    User(String),
+}
+
+/// A code hosting service that repositories can be listed from
+#[async_trait]
+pub trait Forge: Send + Sync {
+    /// The name of the service, such as "GitHub"
+    fn name(&self) -> &'static str;
+
+    /// Lists the repositories belonging to `owner`
+    async fn repos(&self, owner: &Owner) -> Result<Vec<RemoteRepo>>;
+}
+
+/// Creates a client for a forge of the given kind. Unless `api_url` is given,
+/// the public service is used. `token` is used if none is found in the
+/// environment variables for that kind of forge. Lists are requested
+/// `per_page` items at a time, up to the forge's maximum, and responses are
+/// cached in `cache` if they are given.
+pub fn connect(
+    kind: ForgeKind,
+    api_url: Option<&str>,
+    token: Option<&str>,
+    per_page: Option<u32>,
+    cache: Option<PathBuf>,
+) -> Result<Box<dyn Forge>> {
+    Ok(match kind {
+        ForgeKind::Github => {
+            let mut github = Github::new(api_url, token)?;
+            if let Some(per_page) = per_page {
+                github = github.with_per_page(per_page);
+            }
+            if let Some(dir) = cache {
+                github = github.with_cache(dir);
+            }
+            Box::new(github)
+        }
+        ForgeKind::Gitlab => {
+            let mut gitlab = Gitlab::new(api_url, token)?;
+            if let Some(per_page) = per_page {
+                gitlab = gitlab.with_per_page(per_page);
+            }
+            if let Some(dir) = cache {
+                gitlab = gitlab.with_cache(dir);
+            }
+            Box::new(gitlab)
+        }
+        ForgeKind::Gitea => {
+            let mut gitea = Gitea::new(api_url, token)?;
+            if let Some(per_page) = per_page {
+                gitea = gitea.with_per_page(per_page);
+            }
+            if let Some(dir) = cache {
+                gitea = gitea.with_cache(dir);
+            }
+            Box::new(gitea)
+        }
+    })
+}
+
+/// Where a token was found, so that a rejected token can be tracked down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenSource {
+    Env(&'static str),
+    Config,
+    GhCli(PathBuf),
+}
+
+impl fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TokenSource::Env(var) => write!(f, "the {} environment variable", var),
+            TokenSource::Config => write!(f, "the config"),
+            TokenSource::GhCli(ref path) => write!(f, "the gh CLI's '{}'", path.display()),
+        }
+    }
+}
+
+type Token = (String, TokenSource);
+
+/// Finds a token in the first of `vars` that is set, falling back to the one
+/// from the config
+fn find_token(vars: &[&'static str], config: Option<&str>) -> Option<Token> {
+    for var in vars {
+        match env::var(var) {
+            Ok(token) if !token.is_empty() => return Some((token, TokenSource::Env(var))),
+            _ => {}
+        }
+    }
+
+    config.map(|token| (token.to_string(), TokenSource::Config))
+}
+
+/// How long to wait for an exhausted rate limit to reset before giving up
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// How long to wait to connect to a forge, and for a whole response, before
+/// the request fails and may be retried
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a cached response is kept, so that the cache doesn't grow forever
+/// with responses for owners that are never listed again
+const CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The most pages of a list that are fetched, in case their links never end
+const MAX_PAGES: usize = 1000;
+
+/// How many times a request that failed with a transient error is retried
+const RETRIES: u32 = 3;
+
+/// How long to wait before the first retry, doubling with each one after it
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The HTTP plumbing shared by the clients for every forge
+struct Api {
+    http: reqwest::Client,
+    url: String,
+    token: Option<Token>,
+    /// The header a token is sent in, and how its value is formatted
+    auth: (HeaderName, fn(&str) -> String),
+    /// How to provide a token, for when one is missing or rejected
+    hint: &'static str,
+    /// The query parameter that sets how many items a page of a list holds,
+    /// and its value
+    page_size: Option<(&'static str, u32)>,
+    /// The directory responses are cached in, to be revalidated by their ETag
+    cache: Option<PathBuf>,
+    /// How many requests are left until the rate limit resets, and when it
+    /// does, as last reported by the server
+    rate_limit: Mutex<Option<(u64, SystemTime)>>,
+}
+
+/// The body of a successful response to a `GET`, possibly from the cache
+struct Page {
+    body: Vec<u8>,
+    /// The `Link` header, which points to the other pages of a list
+    link: Option<String>,
+}
+
+/// A response stored in the cache
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    etag: String,
+    link: Option<String>,
+    body: String,
+}
+
+impl Api {
+    fn new(
+        url: &str,
+        headers: HeaderMap,
+        token: Option<Token>,
+        auth: (HeaderName, fn(&str) -> String),
+        hint: &'static str,
+    ) -> Result<Api> {
+        let url = url.trim_end_matches('/').to_string();
+        host(&url)?;
+
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("repo-rs/", env!("CARGO_PKG_VERSION")))
+            .default_headers(headers)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Api {
+            http,
+            url,
+            token,
+            auth,
+            hint,
+            page_size: None,
+            cache: None,
+            rate_limit: Mutex::new(None),
+        })
+    }
+
+    /// Requests pages of `size` items, set with the query parameter `param`
+    fn with_page_size(mut self, param: &'static str, size: u32) -> Api {
+        self.page_size = Some((param, size));
+        self
+    }
+
+    /// Caches responses in `dir`, so that unchanged ones don't count against
+    /// the rate limit, after removing the ones that have expired
+    fn with_cache(mut self, dir: PathBuf) -> Api {
+        prune_cache(&dir);
+        self.cache = Some(dir);
+        self
+    }
+
+    /// Gets every page of a list, following the `Link` header. Links to
+    /// other servers are not followed, since the token is sent with every
+    /// request, and neither are links to pages that were already fetched.
+    async fn get_pages<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut fetched = HashSet::new();
+        let mut next = Some(self.first_page(path));
+
+        while let Some(url) = next {
+            if fetched.len() >= MAX_PAGES || !fetched.insert(url.clone()) {
+                break;
+            }
+            let page = self.get(&url).await?;
+            next = page
+                .link
+                .as_deref()
+                .and_then(next_link)
+                .filter(|link| same_origin(link, &self.url));
+            items.extend(serde_json::from_slice::<Vec<T>>(&page.body)?);
+        }
+
+        Ok(items)
+    }
+
+    /// Gets a single object
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}/{}", self.url, path);
+        Ok(serde_json::from_slice(&self.get(&url).await?.body)?)
+    }
+
+    /// Sends `body` as JSON with the given method, such as `POST` to create
+    /// something, and reads the object in the response
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &impl serde::Serialize,
+    ) -> Result<T> {
+        let url = format!("{}/{}", self.url, path);
+        let body = serde_json::to_value(body)?;
+        let response = self
+            .send(method, &url, Some(&body), HeaderMap::new())
+            .await?;
+        Ok(response.json::<T>().await?)
+    }
+
+    /// The URL of the first page of the list at `path`, with the page size
+    fn first_page(&self, path: &str) -> String {
+        let url = format!("{}/{}", self.url, path);
+        match self.page_size {
+            Some((param, size)) => {
+                let separator = if url.contains('?') { '&' } else { '?' };
+                format!("{}{}{}={}", url, separator, param, size)
+            }
+            None => url,
+        }
+    }
+
+    /// Gets `url`, revalidating a cached response if there is one
+    async fn get(&self, url: &str) -> Result<Page> {
+        let cache_file = self.cache.as_ref().map(|dir| dir.join(self.cache_key(url)));
+        let cached = cache_file
+            .as_ref()
+            .and_then(|file| fs::read(file).ok())
+            .and_then(|content| serde_json::from_slice::<CacheEntry>(&content).ok());
+
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = cached.as_ref().and_then(|c| c.etag.parse().ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+
+        let response = self.send(Method::GET, url, None, headers).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached {
+                return Ok(Page {
+                    body: entry.body.into_bytes(),
+                    link: entry.link,
+                });
+            }
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let (link, etag) = (header(LINK), header(ETAG));
+        let body = response.bytes().await?.to_vec();
+
+        // a cache that can't be written only costs a full response next time
+        if let (Some(file), Some(etag), Ok(text)) = (cache_file, etag, std::str::from_utf8(&body)) {
+            let entry = CacheEntry {
+                etag,
+                link: link.clone(),
+                body: text.to_string(),
+            };
+            let _ = serde_json::to_vec(&entry)
+                .map_err(io::Error::from)
+                .and_then(|content| write_private(&file, &content));
+        }
+
+        Ok(Page { body, link })
+    }
+
+    /// Sends a request, waiting out exhausted rate limits that reset soon and
+    /// retrying transient failures of requests that are safe to repeat
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&Value>,
+        headers: HeaderMap,
+    ) -> Result<Response> {
+        let idempotent = !matches!(method, Method::POST | Method::PATCH);
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            self.wait_for_rate_limit().await?;
+
+            let mut request = self
+                .http
+                .request(method.clone(), url)
+                .headers(headers.clone());
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            if let Some((ref token, _)) = self.token {
+                let (ref header, format) = self.auth;
+                request = request.header(header.clone(), format(token));
+            }
+
+            let response = match request.send().await {
+                Ok(response) => response,
+                // nothing reached the server if the connection failed
+                Err(e)
+                    if attempt <= RETRIES && (e.is_connect() || idempotent && e.is_timeout()) =>
+                {
+                    time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            self.note_rate_limit(response.headers());
+            let status = response.status();
+            if status.is_success() || status == StatusCode::NOT_MODIFIED {
+                return Ok(response);
+            }
+
+            if let Some(wait) = rate_limit_wait(status, response.headers()) {
+                if wait > MAX_RATE_LIMIT_WAIT || attempt > RETRIES {
+                    return Err(self.rate_limited(wait));
+                }
+                time::sleep(wait).await;
+                continue;
+            }
+
+            let transient = matches!(status.as_u16(), 500 | 502 | 503 | 504);
+            if transient && idempotent && attempt <= RETRIES {
+                time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+                continue;
+            }
+
+            return Err(self.error(response, url).await);
+        }
+    }
+
+    /// Describes an unsuccessful response
+    async fn error(&self, response: Response, url: &str) -> RepoRsError {
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED {
+            return RepoRsError::Unauthorized(
+                self.host(),
+                self.token.as_ref().map(|(_, source)| source.to_string()),
+                self.hint.to_string(),
+            );
+        }
+
+        let body = response.json::<Value>().await.unwrap_or(Value::Null);
+        let mut message = body["message"]
+            .as_str()
+            .unwrap_or_else(|| status.canonical_reason().unwrap_or(""))
+            .to_string();
+        // validation failures only explain themselves in the list of errors
+        let details = body["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e["message"].as_str())
+            .collect::<Vec<_>>();
+        if !details.is_empty() {
+            message = format!("{}: {}", message, details.join("; "));
+        }
+        // forges hide private resources from anonymous requests
+        if status == StatusCode::NOT_FOUND && self.token.is_none() {
+            message.push_str(". No token was found, so private repositories are hidden");
+        }
+
+        RepoRsError::ApiError(url.to_string(), status.as_u16(), message)
+    }
+
+    /// Remembers the rate limit reported in a response's headers
+    fn note_rate_limit(&self, headers: &HeaderMap) {
+        let number = |name| header_number(headers, name);
+        if let (Some(remaining), Some(reset)) =
+            (number("x-ratelimit-remaining"), number("x-ratelimit-reset"))
+        {
+            let reset = SystemTime::UNIX_EPOCH + Duration::from_secs(reset);
+            *self.rate_limit.lock().unwrap() = Some((remaining, reset));
+        }
+    }
+
+    /// Waits for the rate limit to reset if no requests are left, rather than
+    /// sending one that is bound to be refused
+    async fn wait_for_rate_limit(&self) -> Result<()> {
+        let wait = match *self.rate_limit.lock().unwrap() {
+            Some((0, reset)) => match reset.duration_since(SystemTime::now()) {
+                Ok(wait) => wait,
+                Err(_) => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        if wait > MAX_RATE_LIMIT_WAIT {
+            return Err(self.rate_limited(wait));
+        }
+        time::sleep(wait).await;
+        Ok(())
+    }
+
+    fn rate_limited(&self, wait: Duration) -> RepoRsError {
+        let hint = match self.token {
+            Some(_) => None,
+            None => Some(self.hint.to_string()),
+        };
+        RepoRsError::RateLimited(self.host(), wait, hint)
+    }
+
+    /// The name of the file a response for `url` is cached in. Since the URL
+    /// includes the host and the key includes the token, responses that other
+    /// servers or tokens were allowed to see are never sent back as a match.
+    fn cache_key(&self, url: &str) -> String {
+        let token = match self.token {
+            Some((ref token, _)) => token.as_str(),
+            None => "",
+        };
+        cache_key(&format!("{}\n{}", token, url))
+    }
+
+    fn host(&self) -> String {
+        // the URL was checked when the client was created
+        host(&self.url).unwrap_or_else(|_| self.url.clone())
+    }
+}
+
+/// How long to wait before retrying a request that was refused because of a
+/// rate limit, or `None` if it was refused for another reason
+fn rate_limit_wait(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    // secondary rate limits say how long to wait directly
+    if let Some(secs) = header_number(headers, "retry-after") {
+        return Some(Duration::from_secs(secs));
+    }
+
+    match header_number(headers, "x-ratelimit-remaining") {
+        Some(0) => {
+            let reset = header_number(headers, "x-ratelimit-reset")?;
+            let reset = SystemTime::UNIX_EPOCH + Duration::from_secs(reset);
+            // waiting a second longer makes up for clocks that are a bit off
+            let wait = reset.duration_since(SystemTime::now()).unwrap_or_default();
+            Some(wait + Duration::from_secs(1))
+        }
+        _ => None,
+    }
+}
+
+fn header_number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// A file name derived from `key`. The FNV-1a hash is used since, unlike the
+/// standard library's hashers, it is stable.
+fn cache_key(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}.json", hash)
+}
+
+/// Writes a cache file that only the user can read, in a directory only they
+/// can list, since responses may describe private repositories
+fn write_private(file: &Path, content: &[u8]) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    let mut options = fs::OpenOptions::new();
+    builder.recursive(true);
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+        builder.mode(0o700);
+        options.mode(0o600);
+    }
+
+    if let Some(dir) = file.parent() {
+        builder.create(dir)?;
+        // a directory left by an older version may be readable by others
+        #[cfg(unix)]
+        fs::set_permissions(dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+    }
+    options.open(file)?.write_all(content)
+}
+
+/// Removes the cached responses in `dir` that are older than `CACHE_MAX_AGE`
+fn prune_cache(dir: &Path) {
+    // entries that can't be removed are only revalidated again
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > CACHE_MAX_AGE);
+        if expired {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Whether two URLs have the same scheme, host and port
+fn same_origin(a: &str, b: &str) -> bool {
+    match (url::Url::parse(a), url::Url::parse(b)) {
+        (Ok(a), Ok(b)) => {
+            a.scheme() == b.scheme()
+                && a.host_str() == b.host_str()
+                && a.port_or_known_default() == b.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+/// The host name of a URL
+fn host(url: &str) -> Result<String> {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(String::from))
+        .ok_or_else(|| RepoRsError::InvalidUrl(url.to_string()))
+}
+
+/// Everything but the characters RFC 3986 leaves unreserved
+const RESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Escapes a user, organization or group name for use in a URL path or query
+fn encode(name: &str) -> String {
+    utf8_percent_encode(name, RESERVED).to_string()
+}
+
+/// Extracts the URL of the next page from a `Link` header
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        let is_next = params
+            .split(';')
+            .any(|p| p.trim() == "rel=\"next\"" || p.trim() == "rel=next");
+
+        match is_next {
+            true => Some(
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string(),
+            ),
+            false => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::Mock;
+    use super::{encode, next_link, Api, TokenSource};
+    use crate::error::RepoRsError;
+    use reqwest::header::{HeaderMap, AUTHORIZATION};
+    use serde_json::Value;
+
+    fn plain(token: &str) -> String {
+        token.to_string()
+    }
+
+    fn api(url: &str) -> Api {
+        Api::new(
+            url,
+            HeaderMap::new(),
+            None,
+            (AUTHORIZATION, plain as fn(&str) -> String),
+            "Set a token",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn follows_next_links() {
+        let link = "<https://api.github.com/orgs/a/repos?page=3>; rel=\"next\", \
+                    <https://api.github.com/orgs/a/repos?page=5>; rel=\"last\"";
+        assert_eq!(
+            Some("https://api.github.com/orgs/a/repos?page=3".to_string()),
+            next_link(link)
+        );
+        assert_eq!(
+            None,
+            next_link("<https://api.github.com/orgs/a/repos?page=1>; rel=\"prev\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let mock = Mock::start().await;
+        mock.route("/flaky", 502, &[], r#"{"message": "Bad Gateway"}"#);
+        mock.route("/flaky", 200, &[], r#"{"ok": true}"#);
+
+        let value = api(&mock.url).get_json::<Value>("flaky").await.unwrap();
+        assert_eq!(true, value["ok"]);
+        assert_eq!(2, mock.requests().len());
+    }
+
+    #[tokio::test]
+    async fn fails_on_exhausted_rate_limits() {
+        let mock = Mock::start().await;
+        let headers = [
+            ("X-RateLimit-Remaining", "0"),
+            ("X-RateLimit-Reset", "99999999999"),
+        ];
+        mock.route(
+            "/limited",
+            403,
+            &headers,
+            r#"{"message": "API rate limit exceeded"}"#,
+        );
+
+        let api = api(&mock.url);
+        match api.get_json::<Value>("limited").await {
+            Err(RepoRsError::RateLimited(host, _, hint)) => {
+                assert_eq!("127.0.0.1", host);
+                assert_eq!(Some("Set a token".to_string()), hint);
+            }
+            other => panic!("expected a rate limit error, got {:?}", other),
+        }
+
+        // the exhausted limit is remembered, so no request is wasted on it
+        assert!(api.get_json::<Value>("other").await.is_err());
+        assert_eq!(1, mock.requests().len());
+    }
+
+    #[tokio::test]
+    async fn revalidates_cached_responses() {
+        let mock = Mock::start().await;
+        mock.route("/repos", 200, &[("ETag", "\"v1\"")], r#"[1, 2]"#);
+        mock.route("/repos", 304, &[], "");
+        let cache = tempfile::tempdir().unwrap();
+
+        let api = api(&mock.url).with_cache(cache.path().to_path_buf());
+        assert_eq!(vec![1, 2], api.get_pages::<u32>("repos").await.unwrap());
+        assert_eq!(vec![1, 2], api.get_pages::<u32>("repos").await.unwrap());
+
+        let requests = mock.requests();
+        assert_eq!(None, requests[0].header("if-none-match"));
+        assert_eq!(Some("\"v1\""), requests[1].header("if-none-match"));
+    }
+
+    #[tokio::test]
+    async fn follows_only_new_links_to_the_same_server() {
+        let mock = Mock::start().await;
+        let other = Mock::start().await;
+        let to_other = format!("<{}/repos?page=2>; rel=\"next\"", other.url);
+        mock.route("/repos", 200, &[("Link", &to_other)], r#"[1]"#);
+        other.route("/repos?page=2", 200, &[], r#"[2]"#);
+        let to_self = format!("<{}/loop>; rel=\"next\"", mock.url);
+        mock.route("/loop", 200, &[("Link", &to_self)], r#"[3]"#);
+
+        let api = api(&mock.url);
+        assert_eq!(vec![1], api.get_pages::<u32>("repos").await.unwrap());
+        assert!(other.requests().is_empty());
+
+        assert_eq!(vec![3], api.get_pages::<u32>("loop").await.unwrap());
+        assert_eq!(2, mock.requests().len());
+    }
+
+    #[tokio::test]
+    async fn keeps_cached_responses_private() {
+        let mock = Mock::start().await;
+        mock.route("/repos", 200, &[("ETag", "\"v1\"")], r#"[1]"#);
+        mock.route("/repos", 200, &[("ETag", "\"v2\"")], r#"[1, 2]"#);
+        let cache = tempfile::tempdir().unwrap();
+        let dir = cache.path().join("forge");
+
+        let anonymous = api(&mock.url).with_cache(dir.clone());
+        anonymous.get_pages::<u32>("repos").await.unwrap();
+
+        // a response seen without a token, or with another token from the
+        // same place, is not revalidated with one
+        for token in &["secret", "other"] {
+            let mut with_token = api(&mock.url).with_cache(dir.clone());
+            with_token.token = Some((token.to_string(), TokenSource::Config));
+            with_token.get_pages::<u32>("repos").await.unwrap();
+        }
+        for request in &mock.requests()[1..] {
+            assert_eq!(None, request.header("if-none-match"));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &std::path::Path| {
+                std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+            };
+            assert_eq!(0o700, mode(&dir));
+            for entry in std::fs::read_dir(&dir).unwrap() {
+                assert_eq!(0o600, mode(&entry.unwrap().path()));
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_nested_groups() {
+        assert_eq!("group%2Fsub", encode("group/sub"));
+        assert_eq!("octocat", encode("octocat"));
+        assert_eq!("my%20org%2B1", encode("my org+1"));
+    }
+}
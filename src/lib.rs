@@ -0,0 +1,44 @@
+//! Manage many git repositories at once.
+//!
+//! A [`Config`] holds the set of tracked [`Repo`]s, which are created with a
+//! [`RepoBuilder`]. Each repo can be updated, inspected and have arbitrary
+//! commands run in it asynchronously:
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use repo_rs::{Config, Result, RunSpec};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let config = Config::new(Path::new("repos.toml"))?;
+//!
+//!     for (key, repo) in config.repos_sorted() {
+//!         repo.update_repo(false).await?;
+//!         let output = repo.exec(&RunSpec::new("git", &["log", "-1"])).await?;
+//!         println!("{}: {}", key, String::from_utf8_lossy(&output.stdout));
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The functions in [`ops`] do the same across every tracked repo
+//! concurrently, describing what happens to a [`report::Reporter`].
+
+pub mod config;
+pub mod credentials;
+pub mod doctor;
+pub mod error;
+pub mod forge;
+pub mod ops;
+pub mod predicate;
+pub mod repo;
+pub mod report;
+pub mod scan;
+pub mod settings;
+pub mod summary;
+
+pub use config::Config;
+pub use error::{RepoRsError, Result};
+pub use repo::{Repo, RepoBuilder, RunSpec};
@@ -1,84 +1,153 @@
-use futures::{future::join_all, stream::StreamExt};
-use indicatif::{MultiProgress, ProgressBar};
-use std::path::Path;
-use std::process::Output;
-use std::{env, thread};
-use tokio::task::{JoinError, JoinHandle};
+use futures::stream::{self, StreamExt};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 
 use clap::ArgMatches;
 use colored::*;
 
-use config::Config;
-use error::{RepoRsError, Result, UnwrapOrExit};
-use repo::Repo;
+use failure::{CliResult, Context, ExitCode, Failure};
+use glob::Pattern;
+use prettytable::{format, row, Table};
+use repo_rs::config::{self, Config};
+use repo_rs::credentials::{self, Auth};
+use repo_rs::forge::{self, Forge, Github, NewPullRequest, OpenedPullRequest, Owner};
+use repo_rs::ops;
+use repo_rs::predicate::Predicate;
+use repo_rs::repo::{expand_home, namespaced_key};
+use repo_rs::report::{JsonReporter, Reporter};
+use repo_rs::scan::{self, ScanOptions};
+use repo_rs::settings::{ColorMode, ForgeKind, OutputFormat, Settings};
+use repo_rs::{doctor, Repo, RepoRsError, RunSpec};
+use ui::TextReporter;
 
 mod cli;
-mod config;
-mod error;
-mod github;
-mod repo;
-
-pub fn exit(message: &str) -> ! {
-    let err = clap::Error::with_description(message, clap::ErrorKind::InvalidValue);
-    err.exit();
-}
+mod failure;
+mod ui;
 
-fn pluralize_repos(config: &Config) -> String {
-    let count = config.repos.len();
-    let noun = match count {
-        1 => "repo",
-        _ => "repos",
-    };
-    format!("{} {}", count, noun)
+/// Options shared by most commands. Each is resolved from the command line, the
+/// environment, the config file and the built-in default, in that order.
+struct Options {
+    jobs: Option<usize>,
+    output: OutputFormat,
+    /// The timeout from the config, which repos with their own don't use
+    timeout: Option<u64>,
+    /// The timeout from the command line or environment, which applies to
+    /// every repo
+    timeout_override: Option<u64>,
+    tags: Vec<String>,
+    color: ColorMode,
 }
 
-fn collect_output(header: String, result: Output) -> Option<String> {
-    if !result.stdout.is_empty() || !result.stderr.is_empty() {
-        let mut output = header;
+impl Options {
+    fn resolve(matches: &ArgMatches, settings: &Settings) -> CliResult<Options> {
+        let tags = match matches.values_of("tag") {
+            Some(tags) => tags.map(|t| t.to_string()).collect(),
+            None => match env::var("REPO_RS_TAGS") {
+                Ok(tags) => tags.split(',').map(|t| t.trim().to_string()).collect(),
+                Err(_) => settings.tags.clone(),
+            },
+        };
 
-        if !result.stdout.is_empty() {
-            output.push('\n');
-            output.push_str(
-                &String::from_utf8(result.stdout.clone()).expect("Output is not valid utf-8"),
-            );
+        let jobs = resolve(matches.value_of("jobs"), "REPO_RS_JOBS", settings.jobs)?;
+        if let Some(jobs) = jobs.filter(|&j| j == 0 || j > ops::MAX_JOBS) {
+            return Err(Failure::new(
+                ExitCode::Config,
+                &format!(
+                    "Invalid number of jobs {}: must be from 1 to {}",
+                    jobs,
+                    ops::MAX_JOBS
+                ),
+            ));
         }
 
-        if !result.stderr.is_empty() {
-            output.push('\n');
-            output.push_str(&String::from_utf8(result.stderr).expect("Output is not valid utf-8"));
-        }
+        Ok(Options {
+            jobs,
+            output: resolve(
+                matches.value_of("format"),
+                "REPO_RS_FORMAT",
+                settings.output,
+            )?
+            .unwrap_or_default(),
+            timeout: settings.timeout,
+            timeout_override: resolve(matches.value_of("timeout"), "REPO_RS_TIMEOUT", None)?,
+            // an empty tag can be used to override the default tags
+            tags: tags
+                .into_iter()
+                .filter(|t: &String| !t.is_empty())
+                .collect(),
+            color: resolve(matches.value_of("color"), "REPO_RS_COLOR", settings.color)?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// How many repos to make API requests for concurrently. This is the
+    /// validated number of jobs, since a limit of zero would never start any,
+    /// with a default that stays clear of the forges' abuse limits.
+    fn api_jobs(&self) -> usize {
+        self.jobs.unwrap_or(8)
+    }
 
-        return Some(output);
+    /// The options for running an operation across repos
+    fn operation(&self) -> ops::Options {
+        ops::Options {
+            jobs: self.jobs,
+            timeout: self.timeout,
+            timeout_override: self.timeout_override,
+            fail_fast: false,
+            interruptible: true,
+        }
     }
-    None
 }
 
-async fn join_and_handle_errors(message: &str, tasks: Vec<JoinHandle<Result<()>>>) {
-    let results: Vec<_> = join_all(tasks)
-        .await
-        .into_iter()
-        .collect::<std::result::Result<Vec<Result<()>>, JoinError>>()
-        .unwrap_or_exit("Error with runtime");
+/// Resolves a single setting, preferring the command line value, then the
+/// environment variable, then the value from the config file
+fn resolve<T>(cli: Option<&str>, env_var: &str, config: Option<T>) -> CliResult<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(value) = cli {
+        // clap has already validated command line values
+        return Ok(value.parse().ok());
+    }
 
-    handle_errors(message, results);
+    match env::var(env_var) {
+        Ok(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => Err(Failure::usage(&format!(
+                "Invalid value for {}: {}",
+                env_var, e
+            ))),
+        },
+        Err(_) => Ok(config),
+    }
 }
 
-fn handle_errors(message: &str, results: Vec<Result<()>>) {
-    let errors: Vec<Result<()>> = results.into_iter().filter(|r| r.is_err()).collect();
+fn list(config: &Config) {
+    let mut table = Table::new();
+    let format = format::FormatBuilder::new().padding(1, 1).build();
 
-    if !errors.is_empty() {
-        for e in errors {
-            let error = e.err().unwrap();
-            println!("{}", error);
-        }
-        exit(message);
+    table.set_format(format);
+    for (_, repo) in config.repos_sorted() {
+        table.add_row(row![FW->&repo.key, repo.workdir().display()]);
     }
+
+    table.print_tty(true).expect("Failed to render table");
 }
 
-fn track(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) {
+fn track(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) -> CliResult {
     // this has a default value, so safe to unwrap
     let repo_path = subcmd.value_of("path").unwrap();
 
+    if subcmd.is_present("recursive") {
+        return track_recursive(config, subcmd, Path::new(repo_path), config_file);
+    }
+
     let mut builder = Repo::new(repo_path);
 
     if let Some(key) = subcmd.value_of("key") {
@@ -93,212 +162,1036 @@ fn track(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) {
         builder.branch(branch);
     };
 
-    let repo = builder.build().unwrap_or_exit("Error tracking repository");
+    if let Some(tags) = subcmd.values_of("tags") {
+        tags.for_each(|t| builder.tag(t));
+    }
+
+    if subcmd.is_present("namespaced") {
+        builder.namespaced();
+    }
+
+    let mut repo = builder
+        .build()
+        .context(ExitCode::Failure, "Error tracking repository")?;
+
+    if subcmd.is_present("relative") {
+        repo.path = config.relativize(&repo.workdir());
+    }
+
+    if config.tracks_workdir(&repo.workdir()) {
+        return Err(Failure::usage("Repo is already being tracked"));
+    }
+
+    if config.repos.contains_key(&repo.key) {
+        return Err(Failure::usage(&format!(
+            "A repo is already being tracked as '{}'. Try again with --key '{}'",
+            repo.key,
+            config.suggest_key(&repo)
+        )));
+    }
+
+    println!(
+        "Tracking branch '{}' from remote '{}' of '{}' at '{}'",
+        &repo.branch.white().bold(),
+        &repo.remote.white().bold(),
+        &repo.key.white().bold(),
+        &repo.path.white().bold()
+    );
+    config
+        .add(repo)
+        .context(ExitCode::Failure, "Error tracking repository")?;
+    config
+        .save(config_file)
+        .context(ExitCode::Config, "Error saving config")?;
+
+    Ok(())
+}
+
+fn track_recursive(
+    config: &mut Config,
+    subcmd: &ArgMatches,
+    root: &Path,
+    config_file: &Path,
+) -> CliResult {
+    // clap has already validated these
+    let opts = ScanOptions {
+        max_depth: subcmd.value_of("max-depth").unwrap().parse().unwrap(),
+        ignore: subcmd
+            .values_of("ignore")
+            .map(|patterns| patterns.map(|p| Pattern::new(p).unwrap()).collect())
+            .unwrap_or_default(),
+    };
+
+    let mut found = 0;
+    for path in scan::find_repos(root, &opts) {
+        let mut builder = Repo::new(&path.to_string_lossy());
+
+        if let Some(remote) = subcmd.value_of("remote") {
+            builder.remote(remote);
+        }
+
+        if let Some(tags) = subcmd.values_of("tags") {
+            tags.for_each(|t| builder.tag(t));
+        }
+
+        if subcmd.is_present("namespaced") {
+            builder.namespaced();
+        }
+
+        let mut repo = match builder.build() {
+            Ok(repo) => repo,
+            Err(e) => {
+                println!("{} '{}': {}", "Skipping".yellow(), path.display(), e);
+                continue;
+            }
+        };
+
+        if subcmd.is_present("relative") {
+            repo.path = config.relativize(&repo.workdir());
+        }
+
+        if config.tracks_workdir(&repo.workdir()) {
+            continue;
+        }
+
+        if config.repos.contains_key(&repo.key) {
+            repo.key = config.suggest_key(&repo);
+        }
 
-    if config.contains(&repo) {
-        exit("Repo is already being tracked")
-    } else {
         println!(
-            "Tracking branch '{}' from remote '{}' of '{}' at '{}'",
-            &repo.branch.white().bold(),
-            &repo.remote.white().bold(),
+            "{}: branch '{}' from remote '{}' at '{}'",
             &repo.key.white().bold(),
-            &repo.path.white().bold()
+            &repo.branch,
+            &repo.remote,
+            &repo.path
         );
-        config.add(repo);
         config
-            .save(config_file)
-            .unwrap_or_exit("Error saving config");
+            .add(repo)
+            .context(ExitCode::Failure, "Error tracking repository")?;
+        found += 1;
+    }
+
+    if found == 0 {
+        println!("No untracked repos found under '{}'", root.display());
+        return Ok(());
     }
+
+    if !subcmd.is_present("yes") && !confirm(&format!("Track {} repos?", found)) {
+        println!("Nothing was tracked");
+        return Ok(());
+    }
+
+    config
+        .save(config_file)
+        .context(ExitCode::Config, "Error saving config")?;
+    println!("Tracking {} new repos", found);
+
+    Ok(())
 }
 
-fn untrack(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) {
-    // The following two lines are safe because of the way clap validates params
-    let key = subcmd.value_of("key").unwrap();
-    if config.remove(key) {
+/// Asks the user a yes or no question, defaulting to no
+fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn untrack(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) -> CliResult {
+    let patterns: Vec<&str> = subcmd.values_of("keys").into_iter().flatten().collect();
+    let mut keys = config
+        .select(&patterns)
+        .context(ExitCode::Usage, "Error untracking repos")?;
+
+    if subcmd.is_present("missing") {
+        keys.extend(
+            config
+                .repos_sorted()
+                .into_iter()
+                .filter(|(_, repo)| !repo.workdir().exists())
+                .map(|(key, _)| key.clone()),
+        );
+        keys.sort();
+        keys.dedup();
+    }
+
+    if keys.is_empty() {
+        println!("No repos to untrack");
+        return Ok(());
+    }
+
+    let mut refused = Vec::new();
+    let mut doomed = Vec::new();
+    if subcmd.is_present("delete") {
+        let existing: Vec<_> = keys
+            .iter()
+            .filter(|k| config.repos[*k].workdir().exists())
+            .cloned()
+            .collect();
+
+        for key in &existing {
+            println!("{}", config.repos[key].workdir().display());
+        }
+
+        let prompt = format!("Delete {} working trees?", existing.len());
+        if !existing.is_empty() && !subcmd.is_present("yes") && !confirm(&prompt) {
+            println!("Nothing was untracked");
+            return Ok(());
+        }
+
+        for key in existing {
+            let repo = &config.repos[&key];
+            match repo.ensure_pushed() {
+                Ok(()) => doomed.push((key, repo.workdir())),
+                Err(e) => {
+                    keys.retain(|k| k != &key);
+                    refused.push((key, e));
+                }
+            }
+        }
+    }
+
+    for key in &keys {
+        config.remove(key);
         println!("Stopped tracking {}", key.white().bold());
+    }
+
+    // the config is saved before anything is deleted, so that a failure to
+    // save never leaves it tracking working trees that are gone
+    if !keys.is_empty() {
         config
             .save(config_file)
-            .unwrap_or_exit("Error saving config");
+            .context(ExitCode::Config, "Error saving config")?;
     }
+
+    let mut undeleted = Vec::new();
+    for (key, workdir) in doomed {
+        if let Err(e) = fs::remove_dir_all(&workdir) {
+            undeleted.push((key, e.into()));
+        }
+    }
+
+    let mut messages = Vec::new();
+    if !refused.is_empty() {
+        messages.push(format!(
+            "Kept {} repos that could not be deleted",
+            refused.len()
+        ));
+    }
+    if !undeleted.is_empty() {
+        messages.push(format!(
+            "Stopped tracking {} repos whose working trees could not be deleted",
+            undeleted.len()
+        ));
+    }
+    if !messages.is_empty() {
+        let failure = refused.into_iter().chain(undeleted).fold(
+            Failure::new(ExitCode::PartialFailure, &messages.join(". ")),
+            |f, (key, e): (String, RepoRsError)| f.with_error(Some(&key), e),
+        );
+        return Err(failure);
+    }
+
+    Ok(())
 }
 
-#[tokio::main]
-async fn pull(config: &Config, allow_stash: bool) {
-    let m = MultiProgress::new();
+fn set(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) -> CliResult {
+    // this is required, so safe to unwrap
+    let key = config
+        .resolve(subcmd.value_of("key").unwrap())
+        .context(ExitCode::Usage, "Error changing repo")?;
+    let key = key.as_str();
+    let mut repo = config.repos[key].clone();
 
-    println!("Attempting to update {}", pluralize_repos(config));
+    // the remote is changed first so that the new branch is checked against it
+    if let Some(remote) = subcmd.value_of("remote") {
+        repo.set_remote(remote)
+            .context(ExitCode::Usage, "Error changing remote")?;
+    }
 
-    let tasks: Vec<_> = config
-        .repos_sorted()
-        .into_iter()
-        .map(|(key, repo)| {
-            let s = key.clone().white().bold();
-            let repo = repo.clone();
-
-            let pb = m.add(ProgressBar::new_spinner());
-            pb.enable_steady_tick(120);
-            pb.set_message(format!("Updating {}", s));
-
-            tokio::spawn(async move {
-                match repo.update_repo(allow_stash).await {
-                    Ok(output) => {
-                        let status = match String::from_utf8(output.stdout)
-                            .expect("Invalid Utf-8 in output")
-                            .find("Already up to date")
-                        {
-                            Some(_) => "Unchanged".yellow(),
-                            None => "  Updated".green(),
-                        };
+    if let Some(branch) = subcmd.value_of("branch") {
+        repo.set_branch(branch)
+            .context(ExitCode::Usage, "Error changing branch")?;
+    }
 
-                        pb.finish_with_message(format!("{} {}", status, s));
-                        Ok(())
-                    }
-                    Err(e) => {
-                        pb.finish_with_message(format!("{} {} {}", "   Failed".red().bold(), s, e));
-                        Err(e)
-                    }
-                }
-            })
-        })
-        .collect();
-    // we need this to progress and it isn't built to work with futures
-    let multi = thread::spawn(move || m.join());
+    if let Some(tags) = subcmd.values_of("add-tags") {
+        tags.for_each(|t| repo.add_tag(t));
+    }
 
-    join_and_handle_errors("Not all repos could be updated", tasks).await;
-    multi.join().unwrap().unwrap()
-}
+    if let Some(tags) = subcmd.values_of("remove-tags") {
+        tags.for_each(|t| repo.remove_tag(t));
+    }
 
-#[tokio::main]
-async fn run(config: &Config, raw_cmd: &mut Vec<&str>, quiet: bool) {
+    if let Some(new_key) = subcmd.value_of("new-key") {
+        repo.key = new_key.to_string();
+    }
+
+    let new_key = repo.key.clone();
+    config
+        .update(key, repo)
+        .context(ExitCode::Usage, "Error changing repo")?;
+
+    let repo = &config.repos[&new_key];
     println!(
-        "Running `{}` in {}",
-        raw_cmd.clone().join(" "),
-        pluralize_repos(config)
+        "Tracking branch '{}' from remote '{}' of '{}' at '{}'",
+        &repo.branch.white().bold(),
+        &repo.remote.white().bold(),
+        &repo.key.white().bold(),
+        &repo.path.white().bold()
     );
+    config
+        .save(config_file)
+        .context(ExitCode::Config, "Error saving config")?;
 
-    let args: Vec<String> = raw_cmd.drain(1..).map(|s| s.to_owned()).collect();
-    // this is safe, since we know we had at least one value
-    let prog = raw_cmd.pop().unwrap().to_string();
-
-    let tasks: Vec<_> = config
-        .repos
-        .iter()
-        .map(|(key, repo)| {
-            // FIXME: This whole set of clones is awful, really. It is probably
-            // better to construct the command instance outside of the repo.
-            // - MCL - 2021-08-06
-            let repo = repo.clone();
-            let args = args.clone();
-            let prog = prog.clone();
-            let header = format!("{}", &key.green().bold());
-            tokio::spawn(async move {
-                let args: Vec<_> = args.iter().map(|s| s.as_str()).collect();
-                let result = repo.run(&prog, &args).await?;
-                if let Some(output) = collect_output(header, result) {
-                    println!("{}", output);
-                }
-                Ok(())
-            })
-        })
-        .collect();
+    Ok(())
+}
 
-    if quiet {
-        join_all(tasks).await;
+fn relocate(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) -> CliResult {
+    // these are required, so safe to unwrap
+    let old_root = subcmd.value_of("old-root").unwrap();
+    let new_root = subcmd.value_of("new-root").unwrap();
+    let dry_run = subcmd.is_present("dry-run");
+
+    // tracked paths are absolute or under `~`, so relative roots are taken to
+    // be relative to where the command was run rather than to the config
+    let cwd = env::current_dir().context(ExitCode::Failure, "Error finding current directory")?;
+    let old_root: PathBuf = cwd.join(expand_home(old_root)).components().collect();
+    let new_root = if expand_home(new_root).is_absolute() {
+        new_root.to_string()
     } else {
-        join_and_handle_errors("Not all commands succeeded", tasks).await;
+        let new_root: PathBuf = cwd.join(new_root).components().collect();
+        new_root.to_string_lossy().into_owned()
+    };
+
+    let relocated = config.relocate(&old_root, &new_root);
+
+    if relocated.is_empty() {
+        println!("No tracked repos are under '{}'", old_root.display());
+        return Ok(());
     }
 
-    println!("done")
+    for (key, old, new) in &relocated {
+        println!("{}: '{}' -> '{}'", key.white().bold(), old, new);
+    }
+
+    if !dry_run {
+        config
+            .save(config_file)
+            .context(ExitCode::Config, "Error saving config")?;
+        println!("Relocated {} repos", relocated.len());
+    }
+
+    Ok(())
 }
 
-#[tokio::main]
-async fn status(config: &Config, all: bool) {
-    println!("Getting status of {}", pluralize_repos(config));
-
-    let tasks: Vec<_> = config
-        .repos
-        .iter()
-        .map(|(key, repo)| {
-            let repo = repo.clone();
-            let header = format!("{}", &key.green().bold());
-            tokio::spawn(async move {
-                if let Some(result) = repo.status(!all).await? {
-                    if let Some(output) = collect_output(header, result) {
-                        println!("{}", output);
-                    }
+fn doctor(config: &mut Config, subcmd: &ArgMatches, config_file: &Path) -> CliResult {
+    let fix = subcmd.is_present("fix");
+    let diagnoses = doctor::diagnose(config);
+
+    if diagnoses.is_empty() {
+        println!(
+            "No problems found in {}",
+            ui::pluralize_repos(config.repos.len())
+        );
+        return Ok(());
+    }
+
+    let mut unresolved = 0;
+    let mut fixed = 0;
+    for diagnosis in &diagnoses {
+        let label = match diagnosis.issue.is_warning() {
+            true => "warning".yellow(),
+            false => "error".red(),
+        };
+        println!(
+            "{} {}: {}",
+            label,
+            diagnosis.key.white().bold(),
+            diagnosis.issue
+        );
+
+        match (fix, diagnosis.issue.is_warning()) {
+            (true, _) => match diagnosis.fix(config) {
+                Some(action) => {
+                    println!("{:>7} {}", "fixed".green(), action);
+                    fixed += 1;
                 }
+                None if !diagnosis.issue.is_warning() => unresolved += 1,
+                None => {}
+            },
+            (false, false) => unresolved += 1,
+            (false, true) => {}
+        }
+    }
 
-                Ok(())
-            })
+    if fixed > 0 {
+        config
+            .save(config_file)
+            .context(ExitCode::Config, "Error saving config")?;
+    }
+
+    if unresolved > 0 {
+        let hint = match fix {
+            true => "",
+            false => ", run with --fix to repair them",
+        };
+        return Err(Failure::new(
+            ExitCode::Config,
+            &format!("Found {} unresolved problems{}", unresolved, hint),
+        ));
+    }
+
+    Ok(())
+}
+
+fn reporter(opts: &Options, raw: bool) -> Arc<dyn Reporter> {
+    match opts.output {
+        OutputFormat::Json => Arc::new(JsonReporter::new(io::stdout())),
+        OutputFormat::Plain => Arc::new(TextReporter::new(true, raw)),
+        OutputFormat::Pretty => Arc::new(TextReporter::new(false, raw)),
+    }
+}
+
+fn pull(config: &Config, allow_stash: bool, auth: &Auth, opts: &Options) -> CliResult {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context(ExitCode::Failure, "Error starting the runtime")?;
+    let summary = runtime.block_on(ops::update(
+        config,
+        allow_stash,
+        auth,
+        &opts.operation(),
+        reporter(opts, false),
+    ));
+    // fetches that timed out or were interrupted only stop once libgit2 next
+    // calls back, so don't wait for them
+    runtime.shutdown_background();
+
+    match Failure::from_summary("Not all repos could be updated", summary) {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+struct RunOptions {
+    quiet: bool,
+    fail_fast: bool,
+    predicates: Vec<Predicate>,
+    raw: bool,
+}
+
+#[tokio::main]
+async fn run(config: &Config, spec: RunSpec, run_opts: RunOptions, opts: &Options) -> CliResult {
+    let op_opts = ops::Options {
+        fail_fast: run_opts.fail_fast,
+        ..opts.operation()
+    };
+    let reporter = reporter(opts, run_opts.raw);
+    let summary = ops::run(config, spec, &run_opts.predicates, &op_opts, reporter).await;
+
+    match Failure::from_summary("Not all commands succeeded", summary) {
+        // quiet only ignores failed commands, not interruptions
+        Some(failure) if !run_opts.quiet || failure.code() == ExitCode::Interrupted => Err(failure),
+        _ => Ok(()),
+    }
+}
+
+#[tokio::main]
+async fn status(config: &Config, all: bool, raw: bool, opts: &Options) -> CliResult {
+    let summary = ops::status(config, all, &opts.operation(), reporter(opts, raw)).await;
+
+    match Failure::from_summary("Could not get status of all repos", summary) {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+#[tokio::main]
+async fn list_org_repos(gh: &Github, org_name: &str) -> CliResult {
+    let repos = gh.repos(&Owner::Org(org_name.to_string())).await.context(
+        ExitCode::Failure,
+        "Could not fetch repositories from the specified organization",
+    )?;
+
+    for repo in repos {
+        println!("{}", repo.full_name);
+    }
+
+    Ok(())
+}
+
+/// Which open pull requests to list
+struct PrFilter {
+    mine: bool,
+    review_requested: bool,
+}
+
+/// Lists the open pull requests of every tracked repo hosted on the GitHub
+/// server `gh` talks to, grouped by repo
+#[tokio::main]
+async fn list_pull_requests(
+    config: &Config,
+    gh: &Github,
+    filter: PrFilter,
+    opts: &Options,
+) -> CliResult {
+    let user = match filter.mine || filter.review_requested {
+        true => Some(gh.current_user().await.context(
+            ExitCode::Failure,
+            "Could not determine which GitHub user to filter by",
+        )?),
+        false => None,
+    };
+
+    let mut errors = Vec::new();
+    let targets = hosted_on(config, gh, &mut errors);
+
+    let results = stream::iter(targets)
+        .map(|(repo, name)| async move {
+            let key = repo.key.clone();
+            let prs = gh.pull_requests(&name).await;
+            (key, name, prs)
         })
-        .collect();
+        .buffered(opts.api_jobs())
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut listed = 0;
+    for (key, name, prs) in results {
+        let prs = match prs {
+            Ok(prs) => prs,
+            Err(e) => {
+                errors.push((key, e));
+                continue;
+            }
+        };
+        listed += 1;
 
-    join_and_handle_errors("Could not get status of all repos", tasks).await;
+        let prs = prs
+            .into_iter()
+            .filter(|pr| !filter.mine || Some(&pr.author) == user.as_ref())
+            .filter(|pr| {
+                !filter.review_requested
+                    || user
+                        .as_ref()
+                        .is_some_and(|u| pr.requested_reviewers.contains(u))
+            })
+            .collect::<Vec<_>>();
 
-    println!("done")
+        match opts.output {
+            OutputFormat::Json => {
+                for pr in &prs {
+                    let mut value = serde_json::to_value(pr)
+                        .context(ExitCode::Failure, "Could not serialize pull request")?;
+                    value["repo"] = key.clone().into();
+                    value["name"] = name.clone().into();
+                    println!("{}", value);
+                }
+            }
+            _ => ui::print_pull_requests(&key, &name, &prs),
+        }
+    }
+
+    repo_failures("Could not list all pull requests", errors, listed)
+}
+
+/// The repos hosted on the GitHub server `gh` talks to, along with their
+/// names there. Repos hosted elsewhere are silently left out.
+fn hosted_on<'a>(
+    config: &'a Config,
+    gh: &Github,
+    errors: &mut Vec<(String, RepoRsError)>,
+) -> Vec<(&'a Repo, String)> {
+    let mut targets = Vec::new();
+    for (key, repo) in config.repos_sorted() {
+        let url = match repo.remote_url() {
+            Ok(url) => url,
+            Err(e) => {
+                errors.push((key.clone(), e));
+                continue;
+            }
+        };
+        if credentials::host(&url).as_deref() != Some(gh.host()) {
+            continue;
+        }
+        if let Some(name) = namespaced_key(&url) {
+            targets.push((repo, name));
+        }
+    }
+    targets
 }
 
+/// Fails if any repo did, with a partial failure if others succeeded
+fn repo_failures(message: &str, errors: Vec<(String, RepoRsError)>, succeeded: usize) -> CliResult {
+    let code = match (errors.len(), succeeded) {
+        (0, _) => return Ok(()),
+        (_, 0) => ExitCode::Failure,
+        _ => ExitCode::PartialFailure,
+    };
+    Err(errors
+        .into_iter()
+        .fold(Failure::new(code, message), |failure, (key, e)| {
+            failure.with_error(Some(&key), e)
+        }))
+}
+
+/// The pull request to open in each repo
+struct PrTemplate {
+    title: String,
+    body: String,
+    draft: bool,
+}
+
+/// Pushes the current branch of every tracked repo on the GitHub server `gh`
+/// talks to that is ahead of its default branch, opens a pull request for
+/// each and links the new pull requests to each other
 #[tokio::main]
-async fn list_org_repos(org_name: String) {
-    match github::org_repos(org_name).await {
-        Ok(mut response) => {
-            while let Some(repo_raw) = response.next().await {
-                match repo_raw {
-                    Ok(repo) => println!("{}", repo.full_name),
-                    Err(err) => {
-                        handle_errors(
-                            "Could not fetch all repositories from the specified organization",
-                            vec![Err(RepoRsError::from(err))],
-                        );
-                    }
+async fn create_pull_requests(
+    config: &Config,
+    gh: &Github,
+    template: &PrTemplate,
+    auth: &Auth,
+    opts: &Options,
+) -> CliResult {
+    let mut errors = Vec::new();
+    let targets = hosted_on(config, gh, &mut errors);
+    let op_opts = opts.operation();
+
+    let results = stream::iter(targets)
+        .map(|(repo, name)| {
+            let repo = op_opts.prepare(repo);
+            async move {
+                let opened = open_pull_request(gh, &repo, &name, template, auth).await;
+                (repo.key.clone(), name, opened)
+            }
+        })
+        .buffered(opts.api_jobs())
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut opened = Vec::new();
+    for (key, name, result) in results {
+        match result {
+            Ok(PrOutcome::Opened(pr)) => opened.push((key, name, pr)),
+            Ok(PrOutcome::Skipped(reason)) => match opts.output {
+                OutputFormat::Json => {
+                    println!("{}", serde_json::json!({ "repo": key, "skipped": reason }))
                 }
+                _ => eprintln!("{} {}: {}", "skipped".yellow(), key, reason),
+            },
+            Err(e) => errors.push((key, e)),
+        }
+    }
+
+    // link each new pull request to the others, which GitHub renders as links
+    // and mentions in their timelines
+    for (key, name, pr) in &opened {
+        let related = opened
+            .iter()
+            .filter(|(_, other, _)| other != name)
+            .map(|(_, other, other_pr)| format!("- {}#{}", other, other_pr.number))
+            .collect::<Vec<_>>();
+        if !pr.created || related.is_empty() {
+            continue;
+        }
+
+        let body = match template.body.is_empty() {
+            true => format!("Related pull requests:\n{}", related.join("\n")),
+            false => format!(
+                "{}\n\nRelated pull requests:\n{}",
+                template.body,
+                related.join("\n")
+            ),
+        };
+        if let Err(e) = gh.set_pull_request_body(name, pr.number, &body).await {
+            errors.push((key.clone(), e));
+        }
+    }
+
+    for (key, name, pr) in &opened {
+        match opts.output {
+            OutputFormat::Json => {
+                let mut value = serde_json::to_value(pr)
+                    .context(ExitCode::Failure, "Could not serialize pull request")?;
+                value["repo"] = key.clone().into();
+                value["name"] = name.clone().into();
+                println!("{}", value);
             }
+            _ => match pr.created {
+                true => println!("{}: {}", key.white().bold(), pr.url),
+                false => println!("{}: {} (already open)", key.white().bold(), pr.url),
+            },
+        }
+    }
+
+    repo_failures("Could not open all pull requests", errors, opened.len())
+}
+
+/// What became of the pull request for a repo
+enum PrOutcome {
+    Opened(OpenedPullRequest),
+    /// There was nothing to open a pull request for, for the given reason
+    Skipped(String),
+}
+
+/// Pushes the current branch of `repo` if needed and opens a pull request for
+/// it in the repository named `name`
+async fn open_pull_request(
+    gh: &Github,
+    repo: &Repo,
+    name: &str,
+    template: &PrTemplate,
+    auth: &Auth,
+) -> repo_rs::Result<PrOutcome> {
+    let branch = repo.current_branch()?;
+    let base = match gh.repo(name).await?.default_branch {
+        Some(base) => base,
+        None => {
+            return Ok(PrOutcome::Skipped(
+                "the repository on GitHub is empty".to_string(),
+            ))
         }
-        Err(err) => {
-            handle_errors(
-                "Could not fetch repositories from the specified organization",
-                vec![Err(err)],
+    };
+
+    if branch == base {
+        return Ok(PrOutcome::Skipped(format!(
+            "'{}' is the default branch",
+            branch
+        )));
+    }
+    match repo.ahead_of(&base)? {
+        Some(0) => {
+            return Ok(PrOutcome::Skipped(format!(
+                "'{}' is not ahead of '{}'",
+                branch, base
+            )))
+        }
+        Some(_) => {}
+        None => {
+            return Ok(PrOutcome::Skipped(format!(
+                "'{}/{}' has not been fetched",
+                repo.remote, base
+            )))
+        }
+    }
+
+    if repo.needs_push()? {
+        repo.push(auth).await?;
+    }
+
+    let pr = NewPullRequest {
+        title: template.title.clone(),
+        body: template.body.clone(),
+        head: branch,
+        base,
+        draft: template.draft,
+    };
+    Ok(PrOutcome::Opened(gh.create_pull_request(name, &pr).await?))
+}
+
+/// Connects to the forge named on the command line, or otherwise described by
+/// the kind and API URL given there
+fn connect_forge(config: &Config, matches: &ArgMatches) -> CliResult<Box<dyn Forge>> {
+    let named = match matches.value_of("forge") {
+        Some(name) => match config.settings.forges.get(name) {
+            Some(forge) => Some(forge),
+            None => {
+                return Err(Failure::usage(&format!(
+                    "No forge named '{}' is configured",
+                    name
+                )))
+            }
+        },
+        None => None,
+    };
+
+    // clap has already validated the kind
+    let kind = match matches.value_of("kind") {
+        Some(kind) => kind.parse().unwrap(),
+        None => named.map(|f| f.kind).unwrap_or_default(),
+    };
+    let per_page = matches.value_of("per-page");
+    let (api_url, token, per_page) = match named {
+        Some(forge) => (
+            forge.api_url.as_deref(),
+            forge.token.as_deref(),
+            per_page.and_then(|n| n.parse().ok()).or(forge.per_page),
+        ),
+        None if kind == ForgeKind::Github => (
+            config.settings.github.api_url.as_deref(),
+            config.settings.github.token.as_deref(),
+            resolve(
+                per_page,
+                "REPO_RS_GITHUB_PER_PAGE",
+                config.settings.github.per_page,
+            )?,
+        ),
+        None => (None, None, per_page.and_then(|n| n.parse().ok())),
+    };
+    let cache = config::cache_dir().join(match kind {
+        ForgeKind::Github => "github",
+        ForgeKind::Gitlab => "gitlab",
+        ForgeKind::Gitea => "gitea",
+    });
+
+    forge::connect(
+        kind,
+        matches.value_of("api-url").or(api_url),
+        token,
+        per_page,
+        Some(cache),
+    )
+    .context(ExitCode::Usage, "Could not set up the forge client")
+}
+
+#[tokio::main]
+async fn list_forge_repos(
+    forge: &dyn Forge,
+    owner: Owner,
+    archived: bool,
+    opts: &Options,
+) -> CliResult {
+    let repos = forge.repos(&owner).await.context(
+        ExitCode::Failure,
+        &format!("Could not list repositories from {}", forge.name()),
+    )?;
+    let repos = repos.into_iter().filter(|r| archived || !r.archived);
+
+    if opts.output == OutputFormat::Json {
+        for repo in repos {
+            println!(
+                "{}",
+                serde_json::to_string(&repo)
+                    .context(ExitCode::Failure, "Could not serialize repository")?
             );
         }
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(format::FormatBuilder::new().padding(1, 1).build());
+    for repo in repos {
+        let branch = repo.default_branch.as_deref().unwrap_or("(empty)");
+        let archived = if repo.archived { "archived" } else { "" };
+        table.add_row(row![FW->&repo.full_name, branch, repo.ssh_url, archived]);
     }
+    table.print_tty(true).expect("Failed to render table");
+
+    Ok(())
 }
 
 fn main() {
-    let default_config_path_raw = dirs::home_dir()
-        .expect("could not determine home directory")
-        .join(".repo-rs.json");
-    let default_config_path = default_config_path_raw.to_str().unwrap();
-    let default_track_path_raw = env::current_dir().expect("could not determine current directory");
-    let default_track_path = default_track_path_raw.to_str().unwrap();
-    let matches = cli::get_matches(default_config_path, default_track_path);
+    if let Err(failure) = try_main() {
+        failure.exit();
+    }
+}
+
+fn try_main() -> CliResult {
+    let cwd = env::current_dir().expect("could not determine current directory");
+    let default_track_path = cwd.to_str().unwrap();
+    let matches = cli::get_matches(default_track_path);
+
+    let config_file = match matches.value_of("config") {
+        Some(path) => PathBuf::from(path),
+        None => config::discover(&cwd),
+    };
+    let config_file = config_file.as_path();
+    let modifies_config = match matches.subcommand() {
+        ("track", _) | ("untrack", _) | ("set", _) | ("relocate", _) => true,
+        ("doctor", Some(doctor_matches)) => doctor_matches.is_present("fix"),
+        _ => false,
+    };
+
+    // hold the lock for the whole read-modify-write of the config
+    let _lock = match modifies_config {
+        true => Some(Config::lock(config_file).context(ExitCode::Config, "Error locking config")?),
+        false => None,
+    };
 
-    let config_file = Path::new(matches.value_of("config").unwrap());
-    let mut config = Config::new(config_file).unwrap_or_exit("Error loading config");
+    let mut config = Config::new(config_file).context(ExitCode::Config, "Error loading config")?;
+
+    if config.created {
+        println!(
+            "Config file not found at path \"{}\". Created one.",
+            config_file.display()
+        );
+    }
+
+    // only commands that save the config migrate the file itself
+    if let (Some(migrated), true) = (&config.migrated, modifies_config) {
+        println!(
+            "Migrating config from version {} to {}. The original will be kept at \"{}\".",
+            migrated.from,
+            config.version,
+            migrated.backup.display()
+        );
+    }
+
+    let opts = Options::resolve(&matches, &config.settings)?;
+
+    match opts.color {
+        ColorMode::Always => colored::control::set_override(true),
+        ColorMode::Never => colored::control::set_override(false),
+        ColorMode::Auto => {}
+    }
+
+    // commands that don't modify the config only operate on the selected repos
+    if !matches!(
+        matches.subcommand_name(),
+        Some("track") | Some("untrack") | Some("set") | Some("relocate") | Some("doctor")
+    ) {
+        config.retain_tagged(&opts.tags);
+    }
 
     match matches.subcommand() {
-        ("list", Some(_)) => config.list(),
+        ("list", Some(_)) => {
+            list(&config);
+            Ok(())
+        }
         ("track", Some(track_matches)) => track(&mut config, track_matches, config_file),
         ("untrack", Some(untrack_matches)) => untrack(&mut config, untrack_matches, config_file),
+        ("set", Some(set_matches)) => set(&mut config, set_matches, config_file),
+        ("relocate", Some(relocate_matches)) => {
+            relocate(&mut config, relocate_matches, config_file)
+        }
+        ("doctor", Some(doctor_matches)) => doctor(&mut config, doctor_matches, config_file),
         ("pull", Some(pull_matches)) => {
-            let allow_stash = pull_matches.is_present("stash");
-            pull(&config, allow_stash)
+            let cli_stash = match (
+                pull_matches.is_present("stash"),
+                pull_matches.is_present("no-stash"),
+            ) {
+                (true, _) => Some("true"),
+                (_, true) => Some("false"),
+                _ => None,
+            };
+            let allow_stash =
+                resolve(cli_stash, "REPO_RS_STASH", config.settings.stash)?.unwrap_or(false);
+            let cli_batch = match (
+                pull_matches.is_present("batch"),
+                pull_matches.is_present("no-batch"),
+            ) {
+                (true, _) => Some("true"),
+                (_, true) => Some("false"),
+                _ => None,
+            };
+            let auth = Auth {
+                batch: resolve(cli_batch, "REPO_RS_BATCH", config.settings.batch)?
+                    .unwrap_or_else(|| !io::stdin().is_terminal()),
+                host_keys: config.settings.ssh_keys.clone(),
+            };
+            pull(&config, allow_stash, &auth, &opts)
         }
         ("run", Some(run_matches)) => {
-            let quiet = run_matches.is_present("quiet");
-            let mut raw_cmd: Vec<&str> = run_matches.values_of("cmd").unwrap().collect();
-            run(&config, &mut raw_cmd, quiet)
+            let shell = run_matches.is_present("shell");
+            // this is safe, since clap requires at least one value
+            let raw_cmd: Vec<&str> = run_matches.values_of("cmd").unwrap().collect();
+            let mut spec = RunSpec::new(raw_cmd[0], &raw_cmd[1..]);
+            spec.shell = shell;
+            if let Some(codes) = run_matches.values_of("ok-exit-codes") {
+                // clap has already validated these
+                spec.ok_exit_codes = codes.map(|c| c.parse().unwrap()).collect();
+            }
+
+            let mut predicates = Vec::new();
+            if let Some(files) = run_matches.values_of("if-exists") {
+                predicates.extend(files.map(|f| Predicate::Exists(f.to_string())));
+            }
+            if run_matches.is_present("if-dirty") {
+                predicates.push(Predicate::Dirty);
+            }
+            if let Some(branch) = run_matches.value_of("if-branch") {
+                predicates.push(Predicate::Branch(branch.to_string()));
+            }
+            if run_matches.is_present("if-ahead") {
+                predicates.push(Predicate::Ahead);
+            }
+
+            let run_opts = RunOptions {
+                quiet: run_matches.is_present("quiet"),
+                fail_fast: run_matches.is_present("fail-fast"),
+                predicates,
+                raw: run_matches.is_present("raw"),
+            };
+            run(&config, spec, run_opts, &opts)
         }
         ("status", Some(status_matches)) => {
             let all = status_matches.is_present("all");
-            status(&config, all)
+            let raw = status_matches.is_present("raw");
+            status(&config, all, raw, &opts)
         }
-        ("gh", Some(gh_matches)) => match gh_matches.subcommand() {
-            ("list", Some(list_matches)) => {
-                list_org_repos(list_matches.value_of("org").unwrap().to_string())
+        ("gh", Some(gh_matches)) => {
+            let api_url = resolve(
+                gh_matches.value_of("api-url"),
+                "REPO_RS_GITHUB_API_URL",
+                config.settings.github.api_url.clone(),
+            )?;
+            let per_page = resolve(
+                gh_matches.value_of("per-page"),
+                "REPO_RS_GITHUB_PER_PAGE",
+                config.settings.github.per_page,
+            )?;
+            let mut gh = Github::new(api_url.as_deref(), config.settings.github.token.as_deref())
+                .context(ExitCode::Usage, "Could not set up the GitHub client")?
+                .with_cache(config::cache_dir().join("github"));
+            if let Some(per_page) = per_page {
+                gh = gh.with_per_page(per_page);
             }
-            _ => unreachable!(),
-        },
+
+            match gh_matches.subcommand() {
+                ("list", Some(list_matches)) => {
+                    list_org_repos(&gh, list_matches.value_of("org").unwrap())
+                }
+                ("prs", Some(prs_matches)) => {
+                    let filter = PrFilter {
+                        mine: prs_matches.is_present("mine"),
+                        review_requested: prs_matches.is_present("review-requested"),
+                    };
+                    list_pull_requests(&config, &gh, filter, &opts)
+                }
+                ("pr", Some(pr_matches)) => match pr_matches.subcommand() {
+                    ("create", Some(create_matches)) => {
+                        let pr = PrTemplate {
+                            // this is safe, since clap requires it
+                            title: create_matches.value_of("title").unwrap().to_string(),
+                            body: create_matches.value_of("body").unwrap_or("").to_string(),
+                            draft: create_matches.is_present("draft"),
+                        };
+                        // pushing may ask for credentials unless in batch mode
+                        let auth = Auth {
+                            batch: resolve(None, "REPO_RS_BATCH", config.settings.batch)?
+                                .unwrap_or_else(|| !io::stdin().is_terminal()),
+                            host_keys: config.settings.ssh_keys.clone(),
+                        };
+                        create_pull_requests(&config, &gh, &pr, &auth, &opts)
+                    }
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            }
+        }
+        ("forge", Some(forge_matches)) => {
+            let forge = connect_forge(&config, forge_matches)?;
+
+            match forge_matches.subcommand() {
+                ("list", Some(list_matches)) => {
+                    // this is safe, since clap requires it
+                    let name = list_matches.value_of("owner").unwrap().to_string();
+                    let owner = match list_matches.is_present("user") {
+                        true => Owner::User(name),
+                        false => Owner::Org(name),
+                    };
+                    let archived = list_matches.is_present("archived");
+                    list_forge_repos(forge.as_ref(), owner, archived, &opts)
+                }
+                _ => unreachable!(),
+            }
+        }
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,448 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::signal;
+use tokio::sync::Semaphore;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::config::Config;
+use crate::credentials::Auth;
+use crate::error::{RepoRsError, Result};
+use crate::predicate::Predicate;
+use crate::repo::{Repo, RunSpec};
+use crate::report::{Event, Operation, Reporter};
+use crate::summary::{Outcome, Summary};
+
+/// Options controlling how an operation is carried out across repos
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The maximum number of repos to work in concurrently, or unlimited if
+    /// not set
+    pub jobs: Option<usize>,
+
+    /// The default command timeout, in seconds, for repos without their own
+    pub timeout: Option<u64>,
+
+    /// A command timeout, in seconds, that replaces the repos' own
+    pub timeout_override: Option<u64>,
+
+    /// Cancel all unfinished work after the first failure
+    pub fail_fast: bool,
+
+    /// Cancel all unfinished work when Ctrl-C is pressed
+    pub interruptible: bool,
+}
+
+/// The largest number of jobs that can be run concurrently
+pub const MAX_JOBS: usize = Semaphore::MAX_PERMITS;
+
+impl Options {
+    /// Limits the number of repos worked in concurrently. A limit of zero would
+    /// never let any work start, so at least one repo is always worked in.
+    fn limiter(&self) -> Arc<Semaphore> {
+        let jobs = self.jobs.unwrap_or(MAX_JOBS).clamp(1, MAX_JOBS);
+        Arc::new(Semaphore::new(jobs))
+    }
+
+    /// A copy of `repo` with the command timeouts applied, for working in it
+    /// outside of the operations here
+    pub fn prepare(&self, repo: &Repo) -> Repo {
+        let mut repo = repo.clone().with_default_timeout(self.timeout);
+        if self.timeout_override.is_some() {
+            repo.timeout = self.timeout_override;
+        }
+        repo
+    }
+}
+
+type Task = (String, JoinHandle<Result<String>>);
+
+/// Pulls every repo in `config`, as `Repo::update_repo` does, authenticating
+/// with `auth`
+pub async fn update(
+    config: &Config,
+    allow_stash: bool,
+    auth: &Auth,
+    opts: &Options,
+    reporter: Arc<dyn Reporter>,
+) -> Summary {
+    let keys = keys(config);
+    reporter.report(&Event::Begin {
+        operation: Operation::Update,
+        keys: &keys,
+    });
+
+    let limiter = opts.limiter();
+    let tasks = config
+        .repos_sorted()
+        .into_iter()
+        .map(|(key, repo)| {
+            let auth = auth.clone();
+            spawn(
+                key,
+                repo,
+                opts,
+                &limiter,
+                &reporter,
+                move |key, repo, reporter| async move {
+                    let progress = |message: &str| {
+                        reporter.report(&Event::Progress { key: &key, message });
+                    };
+                    let before = repo.branch_tip()?;
+                    repo.update_with_progress(allow_stash, &auth, &progress)
+                        .await?;
+
+                    Ok(match repo.branch_tip()? == before {
+                        true => "unchanged".to_string(),
+                        false => "updated".to_string(),
+                    })
+                },
+            )
+        })
+        .collect();
+
+    join(Summary::default(), tasks, opts, &reporter).await
+}
+
+/// Gets the `git status` of every repo in `config`. Unless `all` is set, only
+/// dirty repos produce output.
+pub async fn status(
+    config: &Config,
+    all: bool,
+    opts: &Options,
+    reporter: Arc<dyn Reporter>,
+) -> Summary {
+    let keys = keys(config);
+    reporter.report(&Event::Begin {
+        operation: Operation::Status,
+        keys: &keys,
+    });
+
+    let limiter = opts.limiter();
+    let tasks = config
+        .repos_sorted()
+        .into_iter()
+        .map(|(key, repo)| {
+            spawn(
+                key,
+                repo,
+                opts,
+                &limiter,
+                &reporter,
+                move |key, repo, reporter| async move {
+                    match repo.status(!all).await? {
+                        Some(output) => {
+                            reporter.report(&Event::Output {
+                                key: &key,
+                                output: &output,
+                            });
+                            Ok("dirty".to_string())
+                        }
+                        None => Ok("clean".to_string()),
+                    }
+                },
+            )
+        })
+        .collect();
+
+    join(Summary::default(), tasks, opts, &reporter).await
+}
+
+/// Runs a command in every repo in `config` that satisfies all of
+/// `predicates`. Output is reported for failed commands as well as successful
+/// ones.
+pub async fn run(
+    config: &Config,
+    spec: RunSpec,
+    predicates: &[Predicate],
+    opts: &Options,
+    reporter: Arc<dyn Reporter>,
+) -> Summary {
+    let keys = keys(config);
+    reporter.report(&Event::Begin {
+        operation: Operation::Run(&spec),
+        keys: &keys,
+    });
+
+    let spec = Arc::new(spec);
+    let limiter = opts.limiter();
+    let mut summary = Summary::default();
+    let mut tasks = Vec::new();
+
+    'repos: for (key, repo) in config.repos_sorted() {
+        for predicate in predicates {
+            match repo.satisfies(predicate) {
+                Ok(true) => {}
+                Ok(false) => {
+                    let reason = predicate.to_string();
+                    reporter.report(&Event::Skipped {
+                        key,
+                        reason: &reason,
+                    });
+                    summary.push(key.clone(), Outcome::Skipped(reason));
+                    continue 'repos;
+                }
+                Err(e) => {
+                    reporter.report(&Event::Failed { key, error: &e });
+                    summary.push(key.clone(), Outcome::Failed(e));
+                    continue 'repos;
+                }
+            }
+        }
+
+        let spec = spec.clone();
+        tasks.push(spawn(
+            key,
+            repo,
+            opts,
+            &limiter,
+            &reporter,
+            move |key, repo, reporter| async move {
+                let result = repo.exec(&spec).await;
+
+                let output = match result {
+                    Ok(ref output) => Some(output),
+                    Err(RepoRsError::CommandFailed(_, _, ref output)) => Some(output.as_ref()),
+                    Err(_) => None,
+                };
+
+                if let Some(output) = output {
+                    reporter.report(&Event::Output { key: &key, output });
+                }
+
+                result.map(|_| "succeeded".to_string())
+            },
+        ));
+    }
+
+    join(summary, tasks, opts, &reporter).await
+}
+
+fn keys(config: &Config) -> Vec<String> {
+    config
+        .repos_sorted()
+        .into_iter()
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Spawns a task that does `work` in `repo` once the limiter allows it. The
+/// work returns a short description of its result.
+fn spawn<F, Fut>(
+    key: &str,
+    repo: &Repo,
+    opts: &Options,
+    limiter: &Arc<Semaphore>,
+    reporter: &Arc<dyn Reporter>,
+    work: F,
+) -> Task
+where
+    F: FnOnce(String, Repo, Arc<dyn Reporter>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<String>> + Send + 'static,
+{
+    let repo = opts.prepare(repo);
+    let limiter = limiter.clone();
+    let reporter = reporter.clone();
+    let task_key = key.to_string();
+
+    let handle = tokio::spawn(async move {
+        let _permit = limiter.acquire().await;
+        reporter.report(&Event::Started { key: &task_key });
+        work(task_key, repo, reporter).await
+    });
+
+    (key.to_string(), handle)
+}
+
+/// Aborts the given tasks when the user presses Ctrl-C. Since every command is
+/// spawned in a process group that is killed when its task is dropped, aborting
+/// a task also kills its child processes.
+fn abort_on_interrupt(handles: Vec<AbortHandle>) -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            flag.store(true, Ordering::SeqCst);
+            handles.iter().for_each(|h| h.abort());
+        }
+    });
+
+    interrupted
+}
+
+/// Waits for every task, recording the outcome for each repo in `summary`
+/// and reporting it as it happens
+async fn join(
+    mut summary: Summary,
+    tasks: Vec<Task>,
+    opts: &Options,
+    reporter: &Arc<dyn Reporter>,
+) -> Summary {
+    let abort_handles: Vec<_> = tasks.iter().map(|(_, t)| t.abort_handle()).collect();
+    let interrupted = match opts.interruptible {
+        true => abort_on_interrupt(tasks.iter().map(|(_, t)| t.abort_handle()).collect()),
+        false => Arc::new(AtomicBool::new(false)),
+    };
+
+    let mut pending: FuturesUnordered<_> = tasks
+        .into_iter()
+        .map(|(key, task)| async move { (key, task.await) })
+        .collect();
+
+    // a repo may have already failed before its task was spawned
+    if opts.fail_fast && summary.failed() > 0 {
+        abort_handles.iter().for_each(|h| h.abort());
+    }
+
+    while let Some((key, joined)) = pending.next().await {
+        let outcome = match joined {
+            Ok(Ok(message)) => {
+                reporter.report(&Event::Finished {
+                    key: &key,
+                    message: &message,
+                });
+                Outcome::Succeeded
+            }
+            Ok(Err(e)) => {
+                if opts.fail_fast {
+                    abort_handles.iter().for_each(|h| h.abort());
+                }
+                Outcome::Failed(e)
+            }
+            Err(e) if e.is_cancelled() => {
+                let reason = match interrupted.load(Ordering::SeqCst) {
+                    true => "interrupted",
+                    false => "cancelled",
+                };
+                Outcome::Skipped(reason.to_string())
+            }
+            // a panic in one task should not take down the others
+            Err(_) => Outcome::Failed(RepoRsError::Panicked(key.clone())),
+        };
+
+        match outcome {
+            Outcome::Failed(ref error) => reporter.report(&Event::Failed { key: &key, error }),
+            Outcome::Skipped(ref reason) => reporter.report(&Event::Skipped { key: &key, reason }),
+            Outcome::Succeeded => {}
+        }
+        summary.push(key, outcome);
+    }
+
+    summary.interrupted = interrupted.load(Ordering::SeqCst);
+    reporter.report(&Event::End { summary: &summary });
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, update, Options};
+    use crate::config::Config;
+    use crate::credentials::Auth;
+    use crate::predicate::Predicate;
+    use crate::repo::{Repo, RunSpec};
+    use crate::report::Collector;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn reports_each_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        for key in &["a", "b", "c"] {
+            let repo = Repo {
+                key: key.to_string(),
+                path: dir.path().to_string_lossy().into_owned(),
+                ..Default::default()
+            };
+            config.repos.insert(key.to_string(), repo);
+        }
+        std::fs::write(dir.path().join("marker"), "").unwrap();
+        config.repos.get_mut("c").unwrap().path = "/nonexistent".to_string();
+
+        let mut spec = RunSpec::new("echo {key}; test {key} = a", &[]);
+        spec.shell = true;
+        let predicates = [Predicate::Exists("marker".to_string())];
+        let collector = Arc::new(Collector::default());
+
+        let summary = run(
+            &config,
+            spec,
+            &predicates,
+            &Options::default(),
+            collector.clone(),
+        )
+        .await;
+
+        assert_eq!(
+            (1, 1, 1),
+            (summary.succeeded(), summary.failed(), summary.skipped())
+        );
+        let events = collector.events();
+        assert_eq!("begin run a,b,c", events[0]);
+        assert_eq!("end 1 1 1", events[events.len() - 1]);
+        for event in &[
+            "skipped c 'marker' does not exist",
+            "started a",
+            "output a a",
+            "finished a succeeded",
+            "output b b",
+            "failed b",
+        ] {
+            assert!(events.contains(&event.to_string()), "{:?}", events);
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_whether_repos_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=a", "-c", "user.email=a@b"])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q", "--bare", "origin.git"]);
+        git(&["clone", "-q", "origin.git", "a"]);
+        git(&["-C", "a", "checkout", "-q", "-b", "main"]);
+        git(&["-C", "a", "commit", "-q", "--allow-empty", "-m", "one"]);
+        git(&["-C", "a", "push", "-q", "origin", "main"]);
+        git(&["clone", "-q", "-b", "main", "origin.git", "b"]);
+        git(&["-C", "b", "commit", "-q", "--allow-empty", "-m", "two"]);
+        git(&["-C", "b", "push", "-q", "origin", "main"]);
+
+        let mut config = Config::default();
+        for key in &["a", "b"] {
+            let repo = Repo {
+                key: key.to_string(),
+                path: dir.path().join(key).to_string_lossy().into_owned(),
+                remote: "origin".to_string(),
+                branch: "main".to_string(),
+                ..Default::default()
+            };
+            config.repos.insert(key.to_string(), repo);
+        }
+        let collector = Arc::new(Collector::default());
+
+        let summary = update(
+            &config,
+            false,
+            &Auth::default(),
+            &Options::default(),
+            collector.clone(),
+        )
+        .await;
+
+        assert_eq!(2, summary.succeeded());
+        let events = collector.events();
+        for event in &["finished a updated", "finished b unchanged"] {
+            assert!(events.contains(&event.to_string()), "{:?}", events);
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// A condition a tracked repo must satisfy for a command to be run in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    /// The given path, relative to the repo root, exists
+    Exists(String),
+
+    /// The working tree has unstaged changes
+    Dirty,
+
+    /// The repo currently has the given branch checked out
+    Branch(String),
+
+    /// The current branch has commits that are not on its upstream
+    Ahead,
+}
+
+impl fmt::Display for Predicate {
+    /// Describes the predicate as the reason for skipping a repo that does
+    /// not satisfy it
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Predicate::Exists(ref file) => write!(f, "'{}' does not exist", file),
+            Predicate::Dirty => write!(f, "not dirty"),
+            Predicate::Branch(ref branch) => write!(f, "not on branch '{}'", branch),
+            Predicate::Ahead => write!(f, "not ahead of upstream"),
+        }
+    }
+}
@@ -1,24 +1,130 @@
-use crate::error::{RepoRsError, Result};
-use git2::{Repository, RepositoryState};
+use crate::credentials::{Attempts, Auth};
+use crate::error::{CommandFailure, RepoRsError, Result};
+use crate::predicate::Predicate;
+use git2::{
+    Branch, BranchType, CertificateCheckStatus, ErrorClass, ErrorCode, FetchOptions, Oid,
+    RemoteCallbacks, Repository, RepositoryState, StatusOptions,
+};
 use serde_derive::{Deserialize, Serialize};
-use std::path::Path;
-use std::process::Output;
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use std::time::Duration;
 use tokio::process::Command;
+use tokio::{task, time};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A tracked git repository
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Repo {
+    /// The unique identifier of the repo in the config
     pub key: String,
+    /// The working directory, which may be relative or start with `~`
     pub path: String,
+    /// The remote that is pulled from
     pub remote: String,
+    /// The branch that is kept up to date
     pub branch: String,
+    /// The maximum number of seconds any single command may run in this repo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The SSH key file to try first when fetching, which may start with `~`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<String>,
+    /// The directory relative paths are resolved against, set when the config
+    /// is loaded
+    #[serde(skip)]
+    pub base: Option<PathBuf>,
 }
 
 impl Repo {
+    /// Starts building a repo from a path at or inside its working directory
     #[allow(clippy::new_ret_no_self)]
     pub fn new(path: &str) -> RepoBuilder {
         RepoBuilder::new(path)
     }
 
+    /// The absolute path of the repo's working directory
+    pub fn workdir(&self) -> PathBuf {
+        let path = expand_home(&self.path);
+        match self.base {
+            Some(ref base) => base.join(path),
+            None => path,
+        }
+    }
+
+    /// Checks whether this repo has at least one of the given tags
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        self.tags.iter().any(|t| tags.contains(t))
+    }
+
+    /// The URL of the tracked remote
+    pub fn remote_url(&self) -> Result<String> {
+        let repository = self.repository()?;
+        let remote = repository
+            .find_remote(&self.remote)
+            .map_err(|_| RepoRsError::RemoteNotFound(self.key.clone(), self.remote.clone()))?;
+
+        Ok(remote.url().unwrap_or_default().to_string())
+    }
+
+    /// Changes the tracked remote, which must exist in the repository
+    pub fn set_remote(&mut self, remote: &str) -> Result<()> {
+        if self.repository()?.find_remote(remote).is_err() {
+            return Err(RepoRsError::RemoteNotFound(
+                self.key.clone(),
+                remote.to_string(),
+            ));
+        }
+
+        self.remote = remote.to_string();
+        Ok(())
+    }
+
+    /// Changes the tracked branch, which must exist either locally or on the
+    /// tracked remote
+    pub fn set_branch(&mut self, branch: &str) -> Result<()> {
+        let repository = self.repository()?;
+        let local = repository.find_branch(branch, BranchType::Local).is_ok();
+        let remote = repository
+            .find_branch(&format!("{}/{}", self.remote, branch), BranchType::Remote)
+            .is_ok();
+
+        if !local && !remote {
+            return Err(RepoRsError::BranchNotFound(
+                self.key.clone(),
+                branch.to_string(),
+            ));
+        }
+
+        self.branch = branch.to_string();
+        Ok(())
+    }
+
+    pub fn add_tag(&mut self, tag: &str) {
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_owned());
+        }
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) {
+        self.tags.retain(|t| t != tag);
+    }
+
+    /// Applies a default command timeout, in seconds, if this repo does not
+    /// specify its own
+    pub fn with_default_timeout(mut self, timeout: Option<u64>) -> Repo {
+        self.timeout = self.timeout.or(timeout);
+        self
+    }
+
     fn validate_working_state(&self) -> Result<()> {
         let repo = self.repository()?;
 
@@ -37,6 +143,158 @@ impl Repo {
             .unwrap_or(false))
     }
 
+    /// Checks that deleting the working tree would not lose any work, meaning
+    /// it is not a linked worktree, has no linked worktrees or submodules, has
+    /// no uncommitted or stashed changes, and every local branch and a
+    /// detached HEAD are contained in a remote-tracking branch
+    pub fn ensure_pushed(&self) -> Result<()> {
+        let repo = self.repository()?;
+        let unpushed = |reason: String| Err(RepoRsError::UnpushedWork(self.key.clone(), reason));
+
+        if repo.is_worktree() {
+            return unpushed("is a linked worktree".to_string());
+        }
+
+        if !repo.worktrees()?.is_empty() {
+            return unpushed("has linked worktrees".to_string());
+        }
+
+        if !repo.submodules()?.is_empty() {
+            return unpushed("has submodules".to_string());
+        }
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).include_ignored(false);
+        if !repo.statuses(Some(&mut opts))?.is_empty() {
+            return unpushed("has uncommitted changes".to_string());
+        }
+
+        if repo.find_reference("refs/stash").is_ok() {
+            return unpushed("has stashed changes".to_string());
+        }
+
+        let remote_tips = repo
+            .branches(Some(BranchType::Remote))?
+            .filter_map(|b| b.ok()?.0.get().target())
+            .collect::<Vec<_>>();
+
+        let mut tips = Vec::new();
+        if repo.head_detached()? {
+            if let Some(tip) = repo.head()?.target() {
+                tips.push(("detached HEAD".to_string(), tip));
+            }
+        }
+        for branch in repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            if let Some(tip) = branch.get().target() {
+                let name = branch.name()?.unwrap_or("(invalid utf-8)");
+                tips.push((format!("branch '{}'", name), tip));
+            }
+        }
+
+        for (name, tip) in tips {
+            let mut pushed = false;
+            for &remote in &remote_tips {
+                if remote == tip || repo.graph_descendant_of(remote, tip)? {
+                    pushed = true;
+                    break;
+                }
+            }
+
+            if !pushed {
+                return unpushed(format!("has unpushed commits on {}", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of commits on the current branch that are not on its
+    /// upstream, or `None` if the upstream cannot be determined or HEAD is
+    /// detached. If the branch
+    /// has no configured upstream, the branch of the same name on the tracked
+    /// remote is used.
+    fn ahead(&self) -> Result<Option<usize>> {
+        let repo = self.repository()?;
+        // a detached HEAD is on no branch, so it has no upstream either
+        if repo.head_detached()? {
+            return Ok(None);
+        }
+        let head = repo.head()?;
+
+        let local = match head.target() {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+
+        let upstream = match Branch::wrap(head).upstream() {
+            Ok(upstream) => upstream.into_reference(),
+            Err(_) => {
+                let name = format!("refs/remotes/{}/{}", self.remote, self.current_branch()?);
+                match repo.find_reference(&name) {
+                    Ok(reference) => reference,
+                    Err(_) => return Ok(None),
+                }
+            }
+        };
+
+        match upstream.target() {
+            Some(remote) => Ok(Some(repo.graph_ahead_behind(local, remote)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// The number of commits on the current branch that are not on `branch`
+    /// of the tracked remote as last fetched, or `None` if that hasn't been
+    /// fetched
+    pub fn ahead_of(&self, branch: &str) -> Result<Option<usize>> {
+        let repo = self.repository()?;
+        let local = match repo.head()?.target() {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+
+        let name = format!("refs/remotes/{}/{}", self.remote, branch);
+        match repo.find_reference(&name).ok().and_then(|r| r.target()) {
+            Some(remote) => Ok(Some(repo.graph_ahead_behind(local, remote)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the current branch has commits its upstream lacks, or no
+    /// upstream at all
+    pub fn needs_push(&self) -> Result<bool> {
+        Ok(!matches!(self.ahead()?, Some(0)))
+    }
+
+    /// Pushes the current branch to the tracked remote and makes the pushed
+    /// branch its upstream
+    pub async fn push(&self, auth: &Auth) -> Result<Output> {
+        let branch = self.current_branch()?;
+        let mut cmd = Command::new("git");
+        cmd.current_dir(self.workdir())
+            .args(["push", "--set-upstream", &self.remote, &branch]);
+        if auth.batch {
+            cmd.env("GIT_TERMINAL_PROMPT", "0")
+                .env("GCM_INTERACTIVE", "never");
+            if env::var_os("GIT_SSH_COMMAND").is_none() && env::var_os("GIT_SSH").is_none() {
+                cmd.env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+            }
+        }
+
+        self.output(cmd, &[]).await
+    }
+
+    /// Checks whether this repo satisfies the given predicate
+    pub fn satisfies(&self, predicate: &Predicate) -> Result<bool> {
+        match *predicate {
+            Predicate::Exists(ref file) => Ok(self.workdir().join(file).exists()),
+            Predicate::Dirty => self.is_dirty(),
+            Predicate::Branch(ref branch) => Ok(&self.current_branch()? == branch),
+            Predicate::Ahead => Ok(self.ahead()?.map(|n| n > 0).unwrap_or(false)),
+        }
+    }
+
     async fn stash(&self) -> Result<Output> {
         self.run("git", &["stash"]).await
     }
@@ -46,7 +304,8 @@ impl Repo {
         Ok(())
     }
 
-    fn current_branch(&self) -> Result<String> {
+    /// The name of the branch currently checked out
+    pub fn current_branch(&self) -> Result<String> {
         let repo = self.repository()?;
         current_branch(&self.key, &repo)
     }
@@ -55,23 +314,266 @@ impl Repo {
         self.run("git", &["checkout", branch]).await
     }
 
+    /// Rebases the current branch onto the tracked branch of the tracked
+    /// remote, as last fetched
     async fn rebase(&self) -> Result<Output> {
-        self.run("git", &["pull", "--rebase"]).await
+        // like `git pull --rebase`, leave out commits that were rewritten
+        // upstream since the branch was forked from it
+        let upstream = format!("{}/{}", self.remote, self.branch);
+        self.run("git", &["rebase", "--fork-point", &upstream])
+            .await
+    }
+
+    /// The commit the tracked branch points to, or `None` if there is no
+    /// local branch by that name
+    pub fn branch_tip(&self) -> Result<Option<Oid>> {
+        let repo = self.repository()?;
+        let reference = repo.find_reference(&format!("refs/heads/{}", self.branch));
+        Ok(reference.ok().and_then(|r| r.target()))
+    }
+
+    /// Fetches the tracked remote without shelling out to git, so that
+    /// authentication never waits for input that can't be seen. The fetch is
+    /// abandoned if it exceeds the repo's timeout or its task is cancelled.
+    pub async fn fetch(&self, auth: &Auth) -> Result<()> {
+        let repo = self.clone();
+        let auth = auth.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _cancel = CancelOnDrop(cancelled.clone());
+
+        let fetch = task::spawn_blocking(move || repo.fetch_blocking(&auth, &cancelled));
+        let joined = match self.timeout {
+            Some(secs) => {
+                let duration = Duration::from_secs(secs);
+                match time::timeout(duration, fetch).await {
+                    Ok(joined) => joined,
+                    Err(_) => {
+                        return Err(RepoRsError::Timeout(
+                            self.key.clone(),
+                            format!("fetch {}", self.remote),
+                            duration,
+                        ))
+                    }
+                }
+            }
+            None => fetch.await,
+        };
+
+        joined.map_err(|_| RepoRsError::Panicked(self.key.clone()))?
+    }
+
+    fn fetch_blocking(&self, auth: &Auth, cancelled: &AtomicBool) -> Result<()> {
+        let repository = self.repository()?;
+        let mut remote = repository
+            .find_remote(&self.remote)
+            .map_err(|_| RepoRsError::RemoteNotFound(self.key.clone(), self.remote.clone()))?;
+        let url = remote.url().unwrap_or_default().to_string();
+        let attempts = RefCell::new(Attempts::new(auth, &url, self.ssh_key.as_deref()));
+
+        set_server_timeouts();
+        // libgit2 only stops when a callback asks it to, so every one of them
+        // checks whether the fetch was given up on
+        let cancel = || {
+            git2::Error::new(
+                ErrorCode::User,
+                ErrorClass::Callback,
+                "the fetch was cancelled",
+            )
+        };
+        let result = {
+            let mut callbacks = RemoteCallbacks::new();
+            callbacks.credentials(
+                |_, username, allowed| match cancelled.load(Ordering::SeqCst) {
+                    true => Err(cancel()),
+                    false => attempts.borrow_mut().next(username, allowed),
+                },
+            );
+            callbacks.certificate_check(|_, _| match cancelled.load(Ordering::SeqCst) {
+                true => Err(cancel()),
+                false => Ok(CertificateCheckStatus::CertificatePassthrough),
+            });
+            callbacks.sideband_progress(|_| !cancelled.load(Ordering::SeqCst));
+            callbacks.transfer_progress(|_| !cancelled.load(Ordering::SeqCst));
+
+            let mut opts = FetchOptions::new();
+            opts.remote_callbacks(callbacks);
+            remote.fetch(&[] as &[&str], Some(&mut opts), None)
+        };
+
+        let attempts = attempts.into_inner();
+        match result {
+            Ok(()) => {
+                attempts.finish(true);
+                Ok(())
+            }
+            Err(e) if e.code() == ErrorCode::Auth => {
+                attempts.finish(false);
+                Err(RepoRsError::AuthFailed(
+                    self.key.clone(),
+                    url,
+                    attempts.tried,
+                ))
+            }
+            Err(e) if e.code() == ErrorCode::Certificate => {
+                Err(RepoRsError::CertificateRejected(self.key.clone(), url))
+            }
+            Err(e) => Err(e.into()),
+        }
     }
 
+    /// Runs a program in the working directory, failing if it exits
+    /// unsuccessfully
     pub async fn run(&self, prog: &str, args: &[&str]) -> Result<Output> {
         let mut cmd = Command::new(prog);
-        cmd.current_dir(&self.path).args(args);
+        cmd.current_dir(self.workdir()).args(args);
+
+        self.output(cmd, &[]).await
+    }
+
+    /// Runs a user-supplied command in the working directory, with
+    /// placeholders expanded and the repo's environment variables set
+    pub async fn exec(&self, spec: &RunSpec) -> Result<Output> {
+        let mut cmd = if spec.shell {
+            // the command line is parsed by the shell, so anything substituted
+            // into it must be quoted to be taken literally
+            let mut script = self.substitute(&spec.prog, shell_quote);
+            for arg in &spec.args {
+                script.push(' ');
+                script.push_str(&shell_quote(&self.expand(arg)));
+            }
+
+            let shell = env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+            let mut cmd = Command::new(shell);
+            cmd.arg("-c").arg(script);
+            cmd
+        } else {
+            let mut cmd = Command::new(self.expand(&spec.prog));
+            cmd.args(spec.args.iter().map(|arg| self.expand(arg)));
+            cmd
+        };
+
+        cmd.current_dir(self.workdir()).envs(self.env_vars());
+
+        self.output(cmd, &spec.ok_exit_codes).await
+    }
+
+    /// Substitutes the `{key}`, `{path}`, `{remote}` and `{branch}`
+    /// placeholders in `template` with the values for this repo.
+    pub fn expand(&self, template: &str) -> String {
+        self.substitute(template, |value| value.to_string())
+    }
+
+    /// Substitutes the placeholders in `template` with the values for this
+    /// repo, as formatted by `format`. The template is scanned once, so
+    /// placeholders inside the substituted values are left alone.
+    fn substitute(&self, template: &str, format: impl Fn(&str) -> String) -> String {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let name = rest.find('}').map(|end| &rest[1..end]);
+            match name.and_then(|name| self.placeholder(name).map(|value| (name, value))) {
+                Some((name, value)) => {
+                    expanded.push_str(&format(&value));
+                    rest = &rest[name.len() + 2..];
+                }
+                None => {
+                    expanded.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
 
-        let result = cmd.output().await?;
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// The value of the placeholder `{name}`, if there is one by that name
+    fn placeholder(&self, name: &str) -> Option<String> {
+        match name {
+            "key" => Some(self.key.clone()),
+            "path" => Some(self.workdir().to_string_lossy().into_owned()),
+            "remote" => Some(self.remote.clone()),
+            "branch" => Some(self.branch.clone()),
+            _ => None,
+        }
+    }
 
-        if !result.status.success() {
-            return Err(RepoRsError::CommandFailed(self.key.clone(), cmd, result));
+    /// The environment variables describing this repo that are exposed to
+    /// user-supplied commands
+    pub fn env_vars(&self) -> Vec<(&'static str, OsString)> {
+        vec![
+            ("REPO_KEY", OsString::from(&self.key)),
+            ("REPO_PATH", self.workdir().into_os_string()),
+            ("REPO_REMOTE", OsString::from(&self.remote)),
+            ("REPO_BRANCH", OsString::from(&self.branch)),
+        ]
+    }
+
+    async fn output(&self, mut cmd: Command, ok_exit_codes: &[i32]) -> Result<Output> {
+        // if the task driving this command is cancelled, don't leave the
+        // child running in the background. Shells run pipelines in children
+        // of their own, so the child gets a process group that is killed as a
+        // whole.
+        cmd.kill_on_drop(true)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // setpgid is async-signal-safe, so it may run between fork and exec
+        #[cfg(unix)]
+        unsafe {
+            cmd.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            });
+        }
+
+        let child = cmd.spawn()?;
+        let mut group = KillGroupOnDrop(child.id());
+
+        let result = match self.timeout {
+            Some(secs) => {
+                let duration = Duration::from_secs(secs);
+                match time::timeout(duration, child.wait_with_output()).await {
+                    Ok(result) => result?,
+                    // the group is killed when the guard is dropped
+                    Err(_) => {
+                        return Err(RepoRsError::Timeout(
+                            self.key.clone(),
+                            command_line(&cmd),
+                            duration,
+                        ))
+                    }
+                }
+            }
+            None => child.wait_with_output().await?,
+        };
+        // anything the command left running in the background was meant to be
+        group.0 = None;
+
+        let allowed = result
+            .status
+            .code()
+            .map(|code| ok_exit_codes.contains(&code))
+            .unwrap_or(false);
+
+        if !result.status.success() && !allowed {
+            let failure = CommandFailure::new(command_line(&cmd), self.workdir(), &result);
+            return Err(RepoRsError::CommandFailed(
+                self.key.clone(),
+                Box::new(failure),
+                Box::new(result),
+            ));
         }
 
         Ok(result)
     }
 
+    /// Runs `git status`, or returns `None` without running it if
+    /// `require_dirty` is set and the working tree is clean
     pub async fn status(&self, require_dirty: bool) -> Result<Option<Output>> {
         if !require_dirty || self.is_dirty()? {
             return Ok(Some(self.run("git", &["status"]).await?));
@@ -79,7 +581,23 @@ impl Repo {
         Ok(None)
     }
 
+    /// Pulls the tracked branch from the tracked remote with a rebase,
+    /// returning to the original branch afterwards. Uncommitted changes are
+    /// stashed and restored if `allow_stash` is set, and otherwise cause an
+    /// error.
     pub async fn update_repo(&self, allow_stash: bool) -> Result<Output> {
+        self.update_with_progress(allow_stash, &Auth::default(), &|_| {})
+            .await
+    }
+
+    /// Like `update_repo`, but authenticates with `auth` and calls `progress`
+    /// with a description of each step as it starts
+    pub async fn update_with_progress(
+        &self,
+        allow_stash: bool,
+        auth: &Auth,
+        progress: &(dyn Fn(&str) + Send + Sync),
+    ) -> Result<Output> {
         // make sure there are no active merges/rebases/wahtever
         self.validate_working_state()?;
 
@@ -89,6 +607,7 @@ impl Repo {
         // stash if necessary
         if dirty {
             if allow_stash {
+                progress("stashing changes");
                 self.stash().await?;
             } else {
                 return Err(RepoRsError::RepoDirty(self.key.clone()));
@@ -101,41 +620,90 @@ impl Repo {
 
         // switch to target branch if necessary
         if requires_branch_change {
+            progress(&format!("checking out '{}'", self.branch));
             self.checkout(&self.branch).await?;
         }
 
-        // pull and rebase
+        // fetch and rebase
+        progress("fetching");
+        self.fetch(auth).await?;
+        progress("rebasing");
         let output = self.rebase().await?;
 
         // switch to the original branch if necessary
         if requires_branch_change {
+            progress(&format!("checking out '{}'", original_branch));
             self.checkout(&original_branch).await?;
         }
 
         // undo the stash if necessary
         if dirty && allow_stash {
+            progress("restoring stashed changes");
             self.stash_pop().await?;
         }
 
         Ok(output)
     }
 
-    fn repository(&self) -> Result<Repository> {
-        Ok(Repository::discover(&self.path)?)
+    /// Opens the underlying git repository. Its working directory must be the
+    /// tracked path itself, so that a path that is not a repository is never
+    /// mistaken for a repository containing it.
+    pub fn repository(&self) -> Result<Repository> {
+        let workdir = self.workdir();
+        Repository::open(&workdir).map_err(|e| match e.code() {
+            ErrorCode::NotFound => RepoRsError::NoRepo(workdir.display().to_string()),
+            _ => e.into(),
+        })
     }
 }
 
 impl PartialEq for Repo {
     fn eq(&self, other: &Repo) -> bool {
-        self.key == other.key || self.path == other.path
+        self.key == other.key || self.workdir() == other.workdir()
+    }
+}
+
+/// A user-supplied command to be run in every tracked repo
+#[derive(Debug, Clone)]
+pub struct RunSpec {
+    pub prog: String,
+    pub args: Vec<String>,
+    /// Non-zero exit codes that should not be treated as failures
+    pub ok_exit_codes: Vec<i32>,
+    /// Run the command line through `$SHELL -c` instead of executing it directly
+    pub shell: bool,
+}
+
+impl RunSpec {
+    pub fn new(prog: &str, args: &[&str]) -> RunSpec {
+        RunSpec {
+            prog: prog.to_owned(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            ok_exit_codes: Vec::new(),
+            shell: false,
+        }
+    }
+}
+
+impl fmt::Display for RunSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.prog)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
     }
 }
 
+/// Builds a [`Repo`] from a path, filling in anything not specified from the
+/// repository itself
 pub struct RepoBuilder {
     pub key: Option<String>,
     pub path: String,
     pub remote: Option<String>,
     pub branch: Option<String>,
+    pub tags: Vec<String>,
+    pub namespaced: bool,
 }
 
 impl RepoBuilder {
@@ -145,6 +713,8 @@ impl RepoBuilder {
             path: path.to_owned(),
             remote: None,
             branch: None,
+            tags: Vec::new(),
+            namespaced: false,
         }
     }
 
@@ -160,6 +730,21 @@ impl RepoBuilder {
         self.branch = Some(branch.to_owned());
     }
 
+    /// Derive the key as `owner/name` from the remote URL rather than from the
+    /// directory name, if no key was specified
+    pub fn namespaced(&mut self) {
+        self.namespaced = true;
+    }
+
+    pub fn tag(&mut self, tag: &str) {
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_owned());
+        }
+    }
+
+    /// Opens the repository and builds the repo. The key defaults to the
+    /// directory name, the remote to the first one listed and the branch to
+    /// the current one.
     pub fn build(mut self) -> Result<Repo> {
         let p = self.path.clone();
         let path = Path::new(&p);
@@ -178,12 +763,6 @@ impl RepoBuilder {
         let p = self.path.clone();
         let path = Path::new(&p);
 
-        let key = match self.key {
-            Some(k) => k,
-            // attempt to derive key from repo path
-            None => path.file_name().unwrap().to_str().unwrap().to_owned(),
-        };
-
         let remote = match self.remote {
             Some(r) => r,
             None => {
@@ -195,6 +774,20 @@ impl RepoBuilder {
             }
         };
 
+        let namespaced_key = match self.namespaced {
+            true => repository
+                .find_remote(&remote)
+                .ok()
+                .and_then(|r| r.url().and_then(namespaced_key)),
+            false => None,
+        };
+
+        let key = match self.key.or(namespaced_key) {
+            Some(k) => k,
+            // attempt to derive key from repo path
+            None => path.file_name().unwrap().to_str().unwrap().to_owned(),
+        };
+
         let branch = match self.branch {
             Some(b) => b,
             // attempt to track the current branch if one was not specified
@@ -206,10 +799,114 @@ impl RepoBuilder {
             path: self.path.clone(),
             remote,
             branch,
+            timeout: None,
+            tags: self.tags,
+            ssh_key: None,
+            base: None,
         })
     }
 }
 
+/// Derives an `owner/name` key from a remote URL, in either URL or scp-like
+/// `user@host:owner/name.git` form
+pub fn namespaced_key(url: &str) -> Option<String> {
+    let path = match url::Url::parse(url) {
+        Ok(parsed) if parsed.has_host() => parsed.path().to_string(),
+        // scp-like syntax has no scheme, so the path follows the first colon
+        _ => url.split_once(':')?.1.to_string(),
+    };
+
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let mut segments = path.rsplit('/').filter(|s| !s.is_empty());
+    let name = segments.next()?;
+    let owner = segments.next()?;
+
+    Some(format!("{}/{}", owner, name))
+}
+
+/// Expands a leading `~` in `path` to the current user's home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.trim_start_matches('/'),
+        _ => return PathBuf::from(path),
+    };
+
+    match dirs::home_dir() {
+        Some(home) => home.join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// How long libgit2 waits to connect to an HTTP(S) remote, and then for each
+/// read from it, before giving up. libgit2 has no such limit for SSH.
+const SERVER_CONNECT_TIMEOUT_MS: c_int = 30_000;
+const SERVER_READ_TIMEOUT_MS: c_int = 60_000;
+
+/// Sets libgit2's process-wide server timeouts, which git2 doesn't expose, so
+/// that a fetch from an unresponsive remote can't block a thread forever
+fn set_server_timeouts() {
+    // GIT_OPT_SET_SERVER_CONNECT_TIMEOUT and GIT_OPT_SET_SERVER_TIMEOUT, which
+    // libgit2-sys has no constants for yet
+    const SET_CONNECT_TIMEOUT: c_int = 39;
+    const SET_READ_TIMEOUT: c_int = 41;
+
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        libgit2_sys::init();
+        // failing to set them only leaves the system defaults in place
+        unsafe {
+            libgit2_sys::git_libgit2_opts(SET_CONNECT_TIMEOUT, SERVER_CONNECT_TIMEOUT_MS);
+            libgit2_sys::git_libgit2_opts(SET_READ_TIMEOUT, SERVER_READ_TIMEOUT_MS);
+        }
+    });
+}
+
+/// Sets a flag when dropped, which stops blocking work on another thread
+/// that checks it
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn command_line(cmd: &Command) -> String {
+    let cmd = cmd.as_std();
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|s| shell_quote(&s.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes `value` so that a POSIX shell reads it as a single literal word
+fn shell_quote(value: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    match !value.is_empty() && value.chars().all(plain) {
+        true => value.to_string(),
+        false => format!("'{}'", value.replace('\'', "'\\''")),
+    }
+}
+
+/// Kills the process group led by a child when dropped, unless the child has
+/// finished
+struct KillGroupOnDrop(Option<u32>);
+
+impl Drop for KillGroupOnDrop {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            // the child was made the leader of a group with its own id. This
+            // can't fail in a way that matters, since the group may be gone.
+            unsafe {
+                libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
 // helper since we need to do this during the builder as well
 fn current_branch(key: &str, repository: &Repository) -> Result<String> {
     let head = repository.head()?;
@@ -221,15 +918,24 @@ fn current_branch(key: &str, repository: &Repository) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::Repo;
+    use super::{namespaced_key, Repo, RunSpec};
+    use crate::credentials::Auth;
+    use crate::error::RepoRsError;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn equality() {
         let repo1 = Repo {
             key: "foo".to_string(),
             path: "bar".to_string(),
             remote: "baz".to_string(),
             branch: "fez".to_string(),
+            ..Default::default()
         };
 
         let repo2 = Repo {
@@ -237,6 +943,7 @@ mod tests {
             path: "hoof".to_string(),
             remote: "herp".to_string(),
             branch: "derp".to_string(),
+            ..Default::default()
         };
 
         let repo3 = Repo {
@@ -244,10 +951,255 @@ mod tests {
             path: "bar".to_string(),
             remote: "herp1".to_string(),
             branch: "derp1".to_string(),
+            ..Default::default()
         };
 
         assert_eq!(true, repo1 == repo2);
         assert_eq!(true, repo1 == repo3);
         assert_eq!(false, repo2 == repo3);
     }
+
+    #[test]
+    fn workdir() {
+        let home = dirs::home_dir().unwrap();
+        let mut repo = Repo {
+            path: "~/src/foo".to_string(),
+            base: Some(PathBuf::from("/workspace")),
+            ..Default::default()
+        };
+        assert_eq!(home.join("src/foo"), repo.workdir());
+
+        repo.path = "team/foo".to_string();
+        assert_eq!(PathBuf::from("/workspace/team/foo"), repo.workdir());
+
+        repo.path = "/src/foo".to_string();
+        assert_eq!(PathBuf::from("/src/foo"), repo.workdir());
+
+        repo.path = "~foo/bar".to_string();
+        assert_eq!(PathBuf::from("/workspace/~foo/bar"), repo.workdir());
+    }
+
+    #[test]
+    fn expand() {
+        let repo = Repo {
+            key: "foo".to_string(),
+            path: "/src/foo".to_string(),
+            remote: "origin".to_string(),
+            branch: "main".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "/src/foo/{nope} foo origin/main",
+            repo.expand("{path}/{nope} {key} {remote}/{branch}")
+        );
+    }
+
+    #[tokio::test]
+    async fn quotes_placeholders_in_shell_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repo {
+            key: "it's; touch pwned".to_string(),
+            path: dir.path().to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let mut spec = RunSpec::new("printf '%s|' {key}", &["a b", "$(touch pwned)"]);
+        spec.shell = true;
+        let output = repo.exec(&spec).await.unwrap();
+
+        assert_eq!(
+            "it's; touch pwned|a b|$(touch pwned)|",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[tokio::test]
+    async fn substitutes_placeholders_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repo {
+            key: "{branch}".to_string(),
+            path: dir.path().to_string_lossy().into_owned(),
+            branch: "x';touch pwned;'".to_string(),
+            ..Default::default()
+        };
+        assert_eq!("{branch} x';touch pwned;'", repo.expand("{key} {branch}"));
+
+        let mut spec = RunSpec::new("printf '%s|' {key} {branch}", &[]);
+        spec.shell = true;
+        let output = repo.exec(&spec).await.unwrap();
+
+        assert_eq!(
+            "{branch}|x';touch pwned;'|",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kills_pipelines_that_time_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repo {
+            key: "slow".to_string(),
+            path: dir.path().to_string_lossy().into_owned(),
+            timeout: Some(1),
+            ..Default::default()
+        };
+
+        let mut spec = RunSpec::new("sleep 30 | cat & echo $! > pid; wait", &[]);
+        spec.shell = true;
+        assert!(repo.exec(&spec).await.is_err());
+
+        let pid = std::fs::read_to_string(dir.path().join("pid")).unwrap();
+        // killed processes that haven't been reaped yet still show up as
+        // zombies
+        let alive = || {
+            let output = std::process::Command::new("ps")
+                .args(["-o", "stat=", "-p", pid.trim()])
+                .output()
+                .unwrap();
+            output.status.success() && !output.stdout.starts_with(b"Z")
+        };
+        for _ in 0..20 {
+            if !alive() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("the pipeline outlived its timeout");
+    }
+
+    #[test]
+    fn refuses_to_lose_unpushed_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=a", "-c", "user.email=a@b"])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q", "--bare", "origin.git"]);
+        git(&["clone", "-q", "origin.git", "work"]);
+        git(&["-C", "work", "checkout", "-q", "-b", "main"]);
+        git(&["-C", "work", "commit", "-q", "--allow-empty", "-m", "one"]);
+        git(&["-C", "work", "push", "-q", "origin", "main"]);
+
+        let repo = Repo {
+            key: "work".to_string(),
+            path: dir.path().join("work").to_string_lossy().into_owned(),
+            remote: "origin".to_string(),
+            ..Default::default()
+        };
+        let reason = || match repo.ensure_pushed() {
+            Err(RepoRsError::UnpushedWork(_, reason)) => Some(reason),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(()) => None,
+        };
+        assert_eq!(None, reason());
+
+        git(&["-C", "work", "commit", "-q", "--allow-empty", "-m", "two"]);
+        assert!(reason()
+            .unwrap()
+            .starts_with("has unpushed commits on branch"));
+
+        git(&["-C", "work", "checkout", "-q", "--detach"]);
+        git(&["-C", "work", "branch", "-q", "-f", "main", "origin/main"]);
+        assert_eq!(
+            Some("has unpushed commits on detached HEAD".to_string()),
+            reason()
+        );
+        git(&[
+            "-C",
+            "work",
+            "update-ref",
+            "refs/remotes/origin/HEAD",
+            "origin/main",
+        ]);
+        assert_eq!(None, repo.ahead().unwrap());
+    }
+
+    /// Makes a repo whose remote is an HTTP server on localhost that handles
+    /// each connection with `respond`
+    fn http_remote(dir: &Path, respond: fn(TcpStream)) -> Repo {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                thread::spawn(move || respond(stream));
+            }
+        });
+
+        let repository = git2::Repository::init(dir).unwrap();
+        repository.remote("origin", &url).unwrap();
+        Repo {
+            key: "remote".to_string(),
+            path: dir.to_string_lossy().into_owned(),
+            remote: "origin".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_authentication_without_prompting_in_batch_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = http_remote(dir.path(), |mut stream| {
+            let _ = stream.read(&mut [0; 4096]);
+            let _ = stream.write_all(
+                b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"x\"\r\n\
+                  Content-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        });
+        let auth = Auth {
+            batch: true,
+            ..Default::default()
+        };
+
+        match repo.fetch(&auth).await {
+            Err(RepoRsError::AuthFailed(_, _, tried)) => {
+                assert_eq!(vec!["git credential helpers".to_string()], tried)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn times_out_fetches_from_unresponsive_remotes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = http_remote(dir.path(), |stream| {
+            thread::sleep(Duration::from_secs(600));
+            drop(stream);
+        })
+        .with_default_timeout(Some(1));
+
+        // like the CLI, don't wait for the fetch that is still blocked reading
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let started = Instant::now();
+        let result = runtime.block_on(repo.fetch(&Auth::default()));
+        runtime.shutdown_background();
+
+        assert!(matches!(result, Err(RepoRsError::Timeout(_, _, _))));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn namespaced_keys() {
+        for url in &[
+            "https://github.com/org/name.git",
+            "https://github.com/org/name/",
+            "ssh://git@github.com/org/name",
+            "git@github.com:org/name.git",
+            "https://gitlab.com/group/sub/org/name.git",
+        ] {
+            assert_eq!(Some("org/name".to_string()), namespaced_key(url), "{}", url);
+        }
+
+        assert_eq!(None, namespaced_key("/srv/git/name.git"));
+    }
 }
@@ -0,0 +1,177 @@
+use std::io::Write;
+use std::process::Output;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::error::RepoRsError;
+use crate::repo::RunSpec;
+use crate::summary::Summary;
+
+/// The kind of work an operation does in each repo
+#[derive(Debug, Clone, Copy)]
+pub enum Operation<'a> {
+    Update,
+    Status,
+    Run(&'a RunSpec),
+}
+
+impl<'a> Operation<'a> {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Operation::Update => "update",
+            Operation::Status => "status",
+            Operation::Run(_) => "run",
+        }
+    }
+}
+
+/// Something that happened while an operation was running. Events for
+/// different repos may be interleaved, but every repo that is started ends
+/// with exactly one `Finished`, `Failed` or `Skipped` event.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// The operation is about to run in the repos with the given keys
+    Begin {
+        operation: Operation<'a>,
+        keys: &'a [String],
+    },
+
+    /// Work in a repo has started
+    Started { key: &'a str },
+
+    /// A step of the work in a repo has started
+    Progress { key: &'a str, message: &'a str },
+
+    /// A command run in a repo produced output
+    Output { key: &'a str, output: &'a Output },
+
+    /// Work in a repo succeeded, with a short description of the result
+    Finished { key: &'a str, message: &'a str },
+
+    /// Work in a repo failed
+    Failed {
+        key: &'a str,
+        error: &'a RepoRsError,
+    },
+
+    /// A repo was not worked in, or its work was cancelled
+    Skipped { key: &'a str, reason: &'a str },
+
+    /// The operation has finished in every repo
+    End { summary: &'a Summary },
+}
+
+/// Receives the events emitted by operations. Reporters are shared between
+/// the tasks working in each repo, so they must handle events concurrently.
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: &Event);
+}
+
+/// Writes each event as a single line of JSON
+pub struct JsonReporter<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonReporter<W> {
+    pub fn new(writer: W) -> JsonReporter<W> {
+        JsonReporter {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonReporter<W> {
+    fn report(&self, event: &Event) {
+        let mut writer = self.writer.lock().unwrap();
+        // there's not much we can do if the writer has gone away
+        let _ = writeln!(writer, "{}", to_json(event)).and_then(|_| writer.flush());
+    }
+}
+
+fn to_json(event: &Event) -> Value {
+    match *event {
+        Event::Begin { operation, keys } => {
+            let mut value = json!({
+                "event": "begin",
+                "operation": operation.name(),
+                "repos": keys,
+            });
+            if let Operation::Run(spec) = operation {
+                value["command"] = json!(spec.to_string());
+            }
+            value
+        }
+        Event::Started { key } => json!({ "event": "started", "repo": key }),
+        Event::Progress { key, message } => {
+            json!({ "event": "progress", "repo": key, "message": message })
+        }
+        Event::Output { key, output } => json!({
+            "event": "output",
+            "repo": key,
+            "stdout": String::from_utf8_lossy(&output.stdout),
+            "stderr": String::from_utf8_lossy(&output.stderr),
+        }),
+        Event::Finished { key, message } => {
+            json!({ "event": "finished", "repo": key, "message": message })
+        }
+        Event::Failed { key, error } => {
+            let mut value = json!({ "event": "failed", "repo": key, "error": error.to_string() });
+            if let RepoRsError::CommandFailed(_, ref failure, _) = *error {
+                value["command"] = json!(failure);
+            }
+            value
+        }
+        Event::Skipped { key, reason } => {
+            json!({ "event": "skipped", "repo": key, "reason": reason })
+        }
+        Event::End { summary } => json!({
+            "event": "end",
+            "succeeded": summary.succeeded(),
+            "failed": summary.failed(),
+            "skipped": summary.skipped(),
+            "interrupted": summary.interrupted,
+        }),
+    }
+}
+
+/// Records a one-line description of each event, for inspecting what an
+/// operation did in tests
+#[derive(Debug, Default)]
+pub struct Collector {
+    events: Mutex<Vec<String>>,
+}
+
+impl Collector {
+    pub fn events(&self) -> Vec<String> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Reporter for Collector {
+    fn report(&self, event: &Event) {
+        let description = match *event {
+            Event::Begin { operation, keys } => {
+                format!("begin {} {}", operation.name(), keys.join(","))
+            }
+            Event::Started { key } => format!("started {}", key),
+            Event::Progress { key, message } => format!("progress {} {}", key, message),
+            Event::Output { key, output } => format!(
+                "output {} {}",
+                key,
+                String::from_utf8_lossy(&output.stdout).trim_end()
+            ),
+            Event::Finished { key, message } => format!("finished {} {}", key, message),
+            Event::Failed { key, .. } => format!("failed {}", key),
+            Event::Skipped { key, reason } => format!("skipped {} {}", key, reason),
+            Event::End { summary } => format!(
+                "end {} {} {}",
+                summary.succeeded(),
+                summary.failed(),
+                summary.skipped()
+            ),
+        };
+
+        self.events.lock().unwrap().push(description);
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+use walkdir::WalkDir;
+
+/// Options controlling which directories are searched for repos
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// How many directories deep to look below the root
+    pub max_depth: usize,
+
+    /// Directories matching any of these, either by name or by path relative
+    /// to the root, are not searched
+    pub ignore: Vec<Pattern>,
+}
+
+impl ScanOptions {
+    fn is_ignored(&self, root: &Path, path: &Path) -> bool {
+        let rel = path.strip_prefix(root).unwrap_or(path);
+        let name = path.file_name().map(Path::new).unwrap_or(rel);
+
+        self.ignore
+            .iter()
+            .any(|p| p.matches_path(rel) || p.matches_path(name))
+    }
+}
+
+/// Finds the working directories of all git repos under `root`, in sorted
+/// order. Repos nested inside other repos, such as submodules, are not
+/// included.
+pub fn find_repos(root: &Path, opts: &ScanOptions) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut walker = WalkDir::new(root)
+        .max_depth(opts.max_depth)
+        .sort_by_file_name()
+        .into_iter();
+
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // unreadable directories are skipped rather than failing the scan
+            Err(_) => continue,
+        };
+
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+
+        if entry.file_name() == ".git" || opts.is_ignored(root, entry.path()) {
+            walker.skip_current_dir();
+            continue;
+        }
+
+        // `.git` is a file rather than a directory in worktrees and submodules
+        if entry.path().join(".git").exists() {
+            found.push(entry.into_path());
+            walker.skip_current_dir();
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_repos, ScanOptions};
+    use glob::Pattern;
+    use std::fs;
+
+    #[test]
+    fn finds_top_level_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for path in &[
+            "foo/.git",
+            "foo/vendor/bar/.git",
+            "team/baz/.git",
+            "node_modules/qux/.git",
+            "a/b/c/deep/.git",
+        ] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        fs::write(root.join("team/baz/sub"), "").unwrap();
+
+        let opts = ScanOptions {
+            max_depth: 3,
+            ignore: vec![Pattern::new("node_modules").unwrap()],
+        };
+
+        assert_eq!(
+            vec![root.join("foo"), root.join("team/baz")],
+            find_repos(root, &opts)
+        );
+    }
+}
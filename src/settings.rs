@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Defaults for command line options, stored in the `settings` section of the
+/// config. Anything not set here falls back to the built-in default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The maximum number of repos to operate on concurrently
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+
+    /// Whether to stash uncommitted changes before pulling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stash: Option<bool>,
+
+    /// Whether fetching and pushing should fail rather than let credential
+    /// helpers prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<bool>,
+
+    /// SSH key files to try when fetching from each host, by host name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ssh_keys: BTreeMap<String, String>,
+
+    /// How progress and results are displayed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFormat>,
+
+    /// The maximum number of seconds a single command may run in a repo that
+    /// doesn't set its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    /// Only operate on repos with at least one of these tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// When to use colors in output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorMode>,
+
+    /// How to reach and authenticate with GitHub
+    #[serde(skip_serializing_if = "GithubSettings::is_empty")]
+    pub github: GithubSettings,
+
+    /// Other code hosting services that repos can be listed from, by name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub forges: BTreeMap<String, ForgeSettings>,
+
+    /// The directory relative repo paths are resolved against. This may start
+    /// with `~` or be relative to the directory containing the config, which is
+    /// also the default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+}
+
+impl Settings {
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+}
+
+/// The GitHub API to use and the token to authenticate with, stored in the
+/// `settings.github` section of the config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GithubSettings {
+    /// The base URL of the API, such as `https://github.example.com/api/v3`
+    /// for GitHub Enterprise. Defaults to `https://api.github.com`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+
+    /// A personal access token. The `GH_TOKEN` and `GITHUB_TOKEN` environment
+    /// variables take precedence over this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// How many items to request per page of a list, up to the default of 100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u32>,
+}
+
+impl GithubSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == GithubSettings::default()
+    }
+}
+
+/// A code hosting service, stored by name in the `settings.forges` section of
+/// the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForgeSettings {
+    pub kind: ForgeKind,
+
+    /// The base URL of the API, which defaults to that of the public service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+
+    /// An access token. The environment variables for the kind of forge take
+    /// precedence over this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// How many items to request per page of a list, up to the forge's maximum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u32>,
+}
+
+/// The kinds of code hosting service that are supported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeKind {
+    #[default]
+    Github,
+    Gitlab,
+    /// Gitea, or its fork Forgejo
+    Gitea,
+}
+
+impl FromStr for ForgeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(ForgeKind::Github),
+            "gitlab" => Ok(ForgeKind::Gitlab),
+            "gitea" | "forgejo" => Ok(ForgeKind::Gitea),
+            _ => Err(format!("'{}' is not a supported kind of forge", s)),
+        }
+    }
+}
+
+/// How progress and results are displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Animated spinners for in-progress operations
+    #[default]
+    Pretty,
+    /// One line per finished operation, suitable for logs
+    Plain,
+    /// One JSON object per event, for consumption by other programs
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(OutputFormat::Pretty),
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("'{}' is not a valid output format", s)),
+        }
+    }
+}
+
+/// When to use colors in output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("'{}' is not a valid color mode", s)),
+        }
+    }
+}
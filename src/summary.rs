@@ -0,0 +1,93 @@
+use crate::error::RepoRsError;
+
+/// The result of an operation against a single tracked repo
+#[derive(Debug)]
+pub enum Outcome {
+    Succeeded,
+    Failed(RepoRsError),
+    Skipped(String),
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Outcome::Succeeded => "Succeeded",
+            Outcome::Failed(_) => "Failed",
+            Outcome::Skipped(_) => "Skipped",
+        }
+    }
+
+    /// A short explanation of a failure or skip
+    pub fn detail(&self) -> Option<String> {
+        match *self {
+            Outcome::Succeeded => None,
+            Outcome::Failed(RepoRsError::CommandFailed(_, ref failure, _)) => {
+                Some(failure.describe_status())
+            }
+            Outcome::Failed(ref err) => Some(err.to_string()),
+            Outcome::Skipped(ref reason) => Some(reason.clone()),
+        }
+    }
+}
+
+/// Collects the per-repo outcomes of an operation for final reporting
+#[derive(Debug, Default)]
+pub struct Summary {
+    entries: Vec<(String, Outcome)>,
+    /// Whether the operation was cut short by Ctrl-C
+    pub interrupted: bool,
+}
+
+impl Summary {
+    pub fn push(&mut self, key: String, outcome: Outcome) {
+        self.entries.push((key, outcome));
+    }
+
+    /// The outcome for each repo, ordered by key
+    pub fn entries_sorted(&self) -> Vec<&(String, Outcome)> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Succeeded))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Failed(_)))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Skipped(_)))
+    }
+
+    fn count<F>(&self, predicate: F) -> usize
+    where
+        F: Fn(&Outcome) -> bool,
+    {
+        self.entries.iter().filter(|(_, o)| predicate(o)).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, Summary};
+    use crate::error::RepoRsError;
+
+    #[test]
+    fn counts() {
+        let mut summary = Summary::default();
+        summary.push("foo".to_string(), Outcome::Succeeded);
+        summary.push(
+            "bar".to_string(),
+            Outcome::Failed(RepoRsError::NoRepo("bar".to_string())),
+        );
+        summary.push("baz".to_string(), Outcome::Skipped("cancelled".to_string()));
+        summary.push("fez".to_string(), Outcome::Succeeded);
+
+        assert_eq!(2, summary.succeeded());
+        assert_eq!(1, summary.failed());
+        assert_eq!(1, summary.skipped());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::process::Output;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use colored::*;
+use indicatif::{MultiProgress, ProgressBar};
+use prettytable::{format, Cell, Row, Table};
+
+use repo_rs::forge::{CheckState, PullRequest, ReviewState};
+use repo_rs::report::{Event, Operation, Reporter};
+use repo_rs::summary::{Outcome, Summary};
+
+/// Reports operations as human readable text. Repos being updated get an
+/// animated spinner each, unless `plain` is set.
+pub struct TextReporter {
+    plain: bool,
+    raw: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    operation: &'static str,
+    bars: HashMap<String, ProgressBar>,
+    multi: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TextReporter {
+    /// Unless `raw` is set, command output is decoded as UTF-8, replacing any
+    /// invalid sequences
+    pub fn new(plain: bool, raw: bool) -> TextReporter {
+        TextReporter {
+            plain,
+            raw,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn begin_update(&self, state: &mut State, keys: &[String]) {
+        println!("Attempting to update {}", pluralize_repos(keys.len()));
+
+        // spinners aren't drawn without a terminal, so print lines instead
+        if self.plain || !io::stderr().is_terminal() {
+            return;
+        }
+
+        let m = MultiProgress::new();
+        for key in keys {
+            let pb = m.add(ProgressBar::new_spinner());
+            pb.enable_steady_tick(120);
+            pb.set_message(format!("Updating {}", key.white().bold()));
+            state.bars.insert(key.clone(), pb);
+        }
+        // we need this to progress and it isn't built to work with futures
+        state.multi = Some(thread::spawn(move || m.join()));
+    }
+
+    /// Ends the line for a repo being updated
+    fn finish(&self, state: &mut State, key: &str, message: String) {
+        if state.operation != "update" {
+            return;
+        }
+
+        match state.bars.remove(key) {
+            Some(pb) => pb.finish_with_message(message),
+            None => println!("{}", message),
+        }
+    }
+}
+
+impl Reporter for TextReporter {
+    fn report(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+
+        match *event {
+            Event::Begin { operation, keys } => {
+                state.operation = operation.name();
+                match operation {
+                    Operation::Update => self.begin_update(&mut state, keys),
+                    Operation::Status => {
+                        println!("Getting status of {}", pluralize_repos(keys.len()))
+                    }
+                    Operation::Run(spec) => {
+                        println!("Running `{}` in {}", spec, pluralize_repos(keys.len()))
+                    }
+                }
+            }
+            Event::Started { .. } => {}
+            Event::Progress { key, message } => {
+                if let Some(pb) = state.bars.get(key) {
+                    pb.set_message(format!("Updating {} ({})", key.white().bold(), message));
+                }
+            }
+            Event::Output { key, output } => {
+                let header = format!("{}", key.green().bold());
+                if let Some(output) = collect_output(header, output, self.raw) {
+                    print_output(&output);
+                }
+            }
+            Event::Finished { key, message } => {
+                let status = match message {
+                    "unchanged" => "Unchanged".yellow(),
+                    _ => "  Updated".green(),
+                };
+                self.finish(
+                    &mut state,
+                    key,
+                    format!("{} {}", status, key.white().bold()),
+                );
+            }
+            Event::Failed { key, error } => {
+                let message = format!(
+                    "{} {} {}",
+                    "   Failed".red().bold(),
+                    key.white().bold(),
+                    error
+                );
+                match state.operation {
+                    "update" => self.finish(&mut state, key, message),
+                    "status" => println!("{}", message),
+                    // failures are listed in the summary
+                    _ => {}
+                }
+            }
+            Event::Skipped { key, reason } => {
+                let message = format!(
+                    "{} {} ({})",
+                    "  Skipped".yellow(),
+                    key.white().bold(),
+                    reason
+                );
+                self.finish(&mut state, key, message);
+            }
+            Event::End { summary } => {
+                for (_, pb) in state.bars.drain() {
+                    pb.finish_and_clear();
+                }
+                if let Some(multi) = state.multi.take() {
+                    // a failure to draw the spinners is not worth reporting
+                    let _ = multi.join();
+                }
+
+                match state.operation {
+                    "run" => print_summary(summary),
+                    "status" => println!("done"),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+pub fn pluralize_repos(count: usize) -> String {
+    let noun = match count {
+        1 => "repo",
+        _ => "repos",
+    };
+    format!("{} {}", count, noun)
+}
+
+/// Prints the open pull requests of a repo under a header naming it. Repos
+/// without any are left out.
+pub fn print_pull_requests(key: &str, full_name: &str, prs: &[PullRequest]) {
+    if prs.is_empty() {
+        return;
+    }
+
+    match key == full_name {
+        true => println!("{}", full_name.white().bold()),
+        false => println!("{} ({})", full_name.white().bold(), key),
+    }
+
+    let now = SystemTime::now();
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    for pr in prs {
+        let age = pr.age(now).map(format_age).unwrap_or_default();
+        let (review, review_style) = match pr.review {
+            ReviewState::Approved => ("approved", "Fg"),
+            ReviewState::ChangesRequested => ("changes requested", "Fr"),
+            ReviewState::ReviewRequired => ("review required", "Fy"),
+            ReviewState::None => ("", ""),
+        };
+        let (checks, checks_style) = match pr.checks {
+            CheckState::Success => ("passing", "Fg"),
+            CheckState::Failure => ("failing", "Fr"),
+            CheckState::Pending => ("pending", "Fy"),
+            CheckState::None => ("", ""),
+        };
+
+        table.add_row(Row::new(vec![
+            Cell::new(&format!("#{}", pr.number)),
+            Cell::new(&pr.title),
+            Cell::new(&pr.author),
+            Cell::new(&age),
+            Cell::new(if pr.draft { "draft" } else { "" }).style_spec("i"),
+            Cell::new(review).style_spec(review_style),
+            Cell::new(checks).style_spec(checks_style),
+        ]));
+    }
+    table.printstd();
+    println!();
+}
+
+/// Formats a duration as its largest whole unit, such as "3d"
+fn format_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    match minutes {
+        0..=59 => format!("{}m", minutes),
+        60..=1439 => format!("{}h", minutes / 60),
+        1440..=20159 => format!("{}d", minutes / 1440),
+        _ => format!("{}w", minutes / 10080),
+    }
+}
+
+/// Collects the output of a command under the given header. Unless `raw` is
+/// set, the output is decoded as UTF-8, replacing any invalid sequences.
+fn collect_output(header: String, result: &Output, raw: bool) -> Option<Vec<u8>> {
+    if !result.stdout.is_empty() || !result.stderr.is_empty() {
+        let mut output = header.into_bytes();
+
+        for stream in [&result.stdout, &result.stderr] {
+            if !stream.is_empty() {
+                output.push(b'\n');
+                if raw {
+                    output.extend_from_slice(stream);
+                } else {
+                    output.extend_from_slice(String::from_utf8_lossy(stream).as_bytes());
+                }
+            }
+        }
+
+        output.push(b'\n');
+        return Some(output);
+    }
+    None
+}
+
+fn print_output(output: &[u8]) {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    // there's not much we can do if stdout has gone away
+    let _ = handle.write_all(output).and_then(|_| handle.flush());
+}
+
+fn print_summary(summary: &Summary) {
+    println!("\nSummary");
+    for (key, outcome) in summary.entries_sorted() {
+        let label = match *outcome {
+            Outcome::Succeeded => outcome.label().green(),
+            Outcome::Failed(_) => outcome.label().red().bold(),
+            Outcome::Skipped(_) => outcome.label().yellow(),
+        };
+
+        match outcome.detail() {
+            Some(detail) => println!("{:>9} {} ({})", label, key.white().bold(), detail),
+            None => println!("{:>9} {}", label, key.white().bold()),
+        }
+    }
+
+    println!(
+        "{} succeeded, {} failed, {} skipped",
+        summary.succeeded(),
+        summary.failed(),
+        summary.skipped()
+    );
+}